@@ -52,8 +52,8 @@ impl FromStr for ImageResizePathParam {
         if split.len() != 2 {
             anyhow::bail!(IMAGE_RESIZE_PARSE_ERROR)
         } else if let &[width_str, height_str] = split.as_slice() {
-            let width: i32 = width_str.parse()?;
-            let height: i32 = height_str.parse()?;
+            let width = parse_dimension(width_str)?;
+            let height = parse_dimension(height_str)?;
 
             Ok(ImageResizePathParam {
                 target_width: width,
@@ -64,3 +64,539 @@ impl FromStr for ImageResizePathParam {
         }
     }
 }
+
+// Thumbor allows either side to be left out (e.g. `x200`), meaning "work it out from
+// the other side".
+fn parse_dimension(s: &str) -> anyhow::Result<i32> {
+    match s {
+        "" | "-" => Ok(0),
+        _ => Ok(s.parse()?),
+    }
+}
+
+/// A fully parsed Thumbor-style path, i.e. everything after the signature (and after
+/// `meta/` for the metadata endpoint):
+///
+/// `[trim[:top-left|:bottom-right][:tolerance]/][AxB:CxD/][fit-in/][-Wx-H/][halign/][valign/][smart/][filters:name(args)...:name(args)/]image_url`
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct ImageProcessingPath {
+    pub trim: Option<TrimPathParam>,
+    pub crop: Option<CropPathParam>,
+    pub fit_in: Option<FitInPathParam>,
+    pub resize: Option<ImageResizePathParam>,
+    pub horizontal_align: Option<HorizontalAlignPathParam>,
+    pub vertical_align: Option<VerticalAlignPathParam>,
+    pub smart: bool,
+    pub filters: Vec<FilterPathParam>,
+    pub image_url: String,
+}
+
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub struct TrimPathParam {
+    pub from: TrimFromPathParam,
+    pub tolerance: u16,
+}
+
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum TrimFromPathParam {
+    TopLeft,
+    BottomRight,
+}
+
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub struct CropPathParam {
+    pub left: u32,
+    pub top: u32,
+    pub right: u32,
+    pub bottom: u32,
+}
+
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum FitInPathParam {
+    FitIn,
+    FullFitIn,
+    AdaptiveFitIn,
+    AdaptiveFullFitIn,
+}
+
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum HorizontalAlignPathParam {
+    Left,
+    Center,
+    Right,
+}
+
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum VerticalAlignPathParam {
+    Top,
+    Middle,
+    Bottom,
+}
+
+/// A single `name(arg1,arg2)` entry from the `filters:` segment. Arguments are kept as
+/// strings; giving them meaning is left to whoever handles the filter.
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct FilterPathParam {
+    pub name: String,
+    pub args: Vec<String>,
+}
+
+impl<'de> serde::Deserialize<'de> for ImageProcessingPath {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        d.deserialize_str(ImageProcessingPathVisitor)
+    }
+}
+
+struct ImageProcessingPathVisitor;
+
+const IMAGE_PROCESSING_PATH_PARSE_ERROR: &str =
+    "A Thumbor-style path of optional operations followed by an image url";
+
+impl de::Visitor<'_> for ImageProcessingPathVisitor {
+    type Value = ImageProcessingPath;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str(IMAGE_PROCESSING_PATH_PARSE_ERROR)
+    }
+
+    fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        value.parse().map_err(E::custom)
+    }
+}
+
+const FILTERS_PREFIX: &str = "filters:";
+
+impl FromStr for ImageProcessingPath {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let segments: Vec<_> = s.split('/').collect();
+        let mut idx = 0;
+
+        // Each part of the grammar is optional, but they have to come in order, so we
+        // only ever look at the current segment for the next part; the first segment that
+        // matches nothing is where the image url begins.
+        let trim = parse_segment(&segments, &mut idx, parse_trim)?;
+        let crop = parse_segment(&segments, &mut idx, parse_crop)?;
+        let fit_in = parse_segment(&segments, &mut idx, parse_fit_in)?;
+        let resize = parse_segment(&segments, &mut idx, |segment| {
+            if is_resize_segment(segment) {
+                segment.parse().map(Some)
+            } else {
+                Ok(None)
+            }
+        })?;
+        let horizontal_align = parse_segment(&segments, &mut idx, |segment| {
+            Ok(match segment {
+                "left" => Some(HorizontalAlignPathParam::Left),
+                "center" => Some(HorizontalAlignPathParam::Center),
+                "right" => Some(HorizontalAlignPathParam::Right),
+                _ => None,
+            })
+        })?;
+        let vertical_align = parse_segment(&segments, &mut idx, |segment| {
+            Ok(match segment {
+                "top" => Some(VerticalAlignPathParam::Top),
+                "middle" => Some(VerticalAlignPathParam::Middle),
+                "bottom" => Some(VerticalAlignPathParam::Bottom),
+                _ => None,
+            })
+        })?;
+        let smart = parse_segment(&segments, &mut idx, |segment| {
+            Ok((segment == "smart").then_some(()))
+        })?
+        .is_some();
+        let filters = parse_filters_segment(&segments, &mut idx)?;
+
+        let image_url = segments[idx..].join("/");
+        if image_url.is_empty() {
+            anyhow::bail!("No image url found in path [{s}]")
+        }
+
+        Ok(ImageProcessingPath {
+            trim,
+            crop,
+            fit_in,
+            resize,
+            horizontal_align,
+            vertical_align,
+            smart,
+            filters,
+            image_url,
+        })
+    }
+}
+
+fn parse_segment<T, F>(segments: &[&str], idx: &mut usize, parse: F) -> anyhow::Result<Option<T>>
+where
+    F: FnOnce(&str) -> anyhow::Result<Option<T>>,
+{
+    // The last segment can never be an operation: it's at least part of the image url
+    if *idx + 1 >= segments.len() {
+        return Ok(None);
+    }
+    let parsed = parse(segments[*idx])?;
+    if parsed.is_some() {
+        *idx += 1;
+    }
+    Ok(parsed)
+}
+
+fn parse_trim(segment: &str) -> anyhow::Result<Option<TrimPathParam>> {
+    let mut parts = segment.split(':');
+    if parts.next() != Some("trim") {
+        return Ok(None);
+    }
+    let mut trim = TrimPathParam {
+        from: TrimFromPathParam::TopLeft,
+        tolerance: 0,
+    };
+    let mut maybe_next = parts.next();
+    match maybe_next {
+        Some("top-left") => maybe_next = parts.next(),
+        Some("bottom-right") => {
+            trim.from = TrimFromPathParam::BottomRight;
+            maybe_next = parts.next();
+        }
+        _ => {}
+    }
+    if let Some(tolerance) = maybe_next {
+        trim.tolerance = tolerance
+            .parse()
+            .map_err(|_| anyhow::anyhow!("Invalid trim tolerance [{tolerance}] in [{segment}]"))?;
+    }
+    if parts.next().is_some() {
+        anyhow::bail!("Invalid trim segment [{segment}]")
+    }
+    Ok(Some(trim))
+}
+
+fn parse_crop(segment: &str) -> anyhow::Result<Option<CropPathParam>> {
+    let Some((top_left, bottom_right)) = segment.split_once(':') else {
+        return Ok(None);
+    };
+    let (Some((left, top)), Some((right, bottom))) =
+        (parse_coordinates(top_left), parse_coordinates(bottom_right))
+    else {
+        return Ok(None);
+    };
+    Ok(Some(CropPathParam {
+        left,
+        top,
+        right,
+        bottom,
+    }))
+}
+
+// Parses `AxB` where both are unsigned numbers
+pub(crate) fn parse_coordinates(s: &str) -> Option<(u32, u32)> {
+    let (x, y) = s.split_once('x')?;
+    Some((x.parse().ok()?, y.parse().ok()?))
+}
+
+fn parse_fit_in(segment: &str) -> anyhow::Result<Option<FitInPathParam>> {
+    Ok(match segment {
+        "fit-in" => Some(FitInPathParam::FitIn),
+        "full-fit-in" => Some(FitInPathParam::FullFitIn),
+        "adaptive-fit-in" => Some(FitInPathParam::AdaptiveFitIn),
+        "adaptive-full-fit-in" => Some(FitInPathParam::AdaptiveFullFitIn),
+        _ => None,
+    })
+}
+
+// `-?\d*x-?\d*`, with a digit on at least one side (a bare `x` is no size at all)
+fn is_resize_segment(segment: &str) -> bool {
+    let Some((width, height)) = segment.split_once('x') else {
+        return false;
+    };
+    let width = width.strip_prefix('-').unwrap_or(width);
+    let height = height.strip_prefix('-').unwrap_or(height);
+    let is_digits = |s: &str| s.chars().all(|c| c.is_ascii_digit());
+    is_digits(width) && is_digits(height) && !(width.is_empty() && height.is_empty())
+}
+
+fn parse_filters_segment(
+    segments: &[&str],
+    idx: &mut usize,
+) -> anyhow::Result<Vec<FilterPathParam>> {
+    if *idx + 1 >= segments.len() || !segments[*idx].starts_with(FILTERS_PREFIX) {
+        return Ok(Vec::new());
+    }
+    // Filter arguments (e.g. urls) may contain slashes, so keep consuming segments until
+    // the parentheses balance out.
+    let mut filters_str = segments[*idx].to_string();
+    *idx += 1;
+    while paren_depth(&filters_str) > 0 && *idx + 1 < segments.len() {
+        filters_str.push('/');
+        filters_str.push_str(segments[*idx]);
+        *idx += 1;
+    }
+    let filters_str = &filters_str[FILTERS_PREFIX.len()..];
+    split_top_level(filters_str, ':')
+        .into_iter()
+        .filter(|s| !s.is_empty())
+        .map(|s| s.parse())
+        .collect()
+}
+
+impl FromStr for FilterPathParam {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, rest) = s
+            .split_once('(')
+            .ok_or_else(|| anyhow::anyhow!("Filter [{s}] should look like name(args)"))?;
+        let args_str = rest
+            .strip_suffix(')')
+            .ok_or_else(|| anyhow::anyhow!("Filter [{s}] is missing a closing parenthesis"))?;
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            anyhow::bail!("Invalid filter name [{name}]")
+        }
+        let args = if args_str.trim().is_empty() {
+            Vec::new()
+        } else {
+            split_top_level(args_str, ',')
+                .into_iter()
+                .map(|a| a.trim().to_string())
+                .collect()
+        };
+        Ok(FilterPathParam {
+            name: name.to_string(),
+            args,
+        })
+    }
+}
+
+fn paren_depth(s: &str) -> i32 {
+    s.chars().fold(0, |depth, c| match c {
+        '(' => depth + 1,
+        ')' => depth - 1,
+        _ => depth,
+    })
+}
+
+// Splits on the separator, ignoring any that are nested inside parentheses
+fn split_top_level(s: &str, separator: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in s.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            c if c == separator && depth == 0 => {
+                parts.push(&s[start..i]);
+                start = i + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    parts.push(&s[start..]);
+    parts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const URL: &str = "https://beachape.com/images/octopress_with_container.png";
+
+    #[test]
+    fn test_parse_image_url_only() -> anyhow::Result<()> {
+        let r: ImageProcessingPath = URL.parse()?;
+        assert_eq!(
+            ImageProcessingPath {
+                trim: None,
+                crop: None,
+                fit_in: None,
+                resize: None,
+                horizontal_align: None,
+                vertical_align: None,
+                smart: false,
+                filters: Vec::new(),
+                image_url: URL.to_string(),
+            },
+            r
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_resize_only() -> anyhow::Result<()> {
+        let r: ImageProcessingPath = format!("-200x100/{URL}").parse()?;
+        assert_eq!(
+            Some(ImageResizePathParam {
+                target_width: -200,
+                target_height: 100
+            }),
+            r.resize
+        );
+        assert_eq!(URL, r.image_url);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_partial_resize() -> anyhow::Result<()> {
+        let r: ImageProcessingPath = format!("x100/{URL}").parse()?;
+        assert_eq!(
+            Some(ImageResizePathParam {
+                target_width: 0,
+                target_height: 100
+            }),
+            r.resize
+        );
+        let r: ImageProcessingPath = format!("300x/{URL}").parse()?;
+        assert_eq!(
+            Some(ImageResizePathParam {
+                target_width: 300,
+                target_height: 0
+            }),
+            r.resize
+        );
+
+        // Without any digits, it's not a size but the start of the url
+        for not_a_size in ["x", "-x-", "-x"] {
+            let r: ImageProcessingPath = format!("{not_a_size}/{URL}").parse()?;
+            assert_eq!(None, r.resize);
+            assert_eq!(format!("{not_a_size}/{URL}"), r.image_url);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_everything() -> anyhow::Result<()> {
+        let r: ImageProcessingPath = format!(
+            "trim:bottom-right:10/10x20:300x400/adaptive-full-fit-in/-200x-100/right/bottom/smart/filters:quality(80):focal(1x2:3x4):fill(blur)/{URL}"
+        )
+        .parse()?;
+        assert_eq!(
+            ImageProcessingPath {
+                trim: Some(TrimPathParam {
+                    from: TrimFromPathParam::BottomRight,
+                    tolerance: 10,
+                }),
+                crop: Some(CropPathParam {
+                    left: 10,
+                    top: 20,
+                    right: 300,
+                    bottom: 400,
+                }),
+                fit_in: Some(FitInPathParam::AdaptiveFullFitIn),
+                resize: Some(ImageResizePathParam {
+                    target_width: -200,
+                    target_height: -100,
+                }),
+                horizontal_align: Some(HorizontalAlignPathParam::Right),
+                vertical_align: Some(VerticalAlignPathParam::Bottom),
+                smart: true,
+                filters: vec![
+                    FilterPathParam {
+                        name: "quality".to_string(),
+                        args: vec!["80".to_string()],
+                    },
+                    FilterPathParam {
+                        name: "focal".to_string(),
+                        args: vec!["1x2:3x4".to_string()],
+                    },
+                    FilterPathParam {
+                        name: "fill".to_string(),
+                        args: vec!["blur".to_string()],
+                    },
+                ],
+                image_url: URL.to_string(),
+            },
+            r
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_trim_variants() -> anyhow::Result<()> {
+        let r: ImageProcessingPath = format!("trim/{URL}").parse()?;
+        assert_eq!(
+            Some(TrimPathParam {
+                from: TrimFromPathParam::TopLeft,
+                tolerance: 0
+            }),
+            r.trim
+        );
+        let r: ImageProcessingPath = format!("trim:top-left/{URL}").parse()?;
+        assert_eq!(
+            Some(TrimPathParam {
+                from: TrimFromPathParam::TopLeft,
+                tolerance: 0
+            }),
+            r.trim
+        );
+        let r: ImageProcessingPath = format!("trim:25/{URL}").parse()?;
+        assert_eq!(
+            Some(TrimPathParam {
+                from: TrimFromPathParam::TopLeft,
+                tolerance: 25
+            }),
+            r.trim
+        );
+        assert!(format!("trim:sideways/{URL}")
+            .parse::<ImageProcessingPath>()
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_out_of_order_segments_end_up_in_url() -> anyhow::Result<()> {
+        let r: ImageProcessingPath = format!("smart/100x100/{URL}").parse()?;
+        assert!(r.smart);
+        assert_eq!(None, r.resize);
+        assert_eq!(format!("100x100/{URL}"), r.image_url);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_filters_with_slashes_and_commas() -> anyhow::Result<()> {
+        let r: ImageProcessingPath =
+            format!("filters:watermark(https://x.com/a.png,10,20,50):grayscale()/{URL}").parse()?;
+        assert_eq!(
+            vec![
+                FilterPathParam {
+                    name: "watermark".to_string(),
+                    args: vec![
+                        "https://x.com/a.png".to_string(),
+                        "10".to_string(),
+                        "20".to_string(),
+                        "50".to_string()
+                    ],
+                },
+                FilterPathParam {
+                    name: "grayscale".to_string(),
+                    args: Vec::new(),
+                },
+            ],
+            r.filters
+        );
+        assert_eq!(URL, r.image_url);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_bad_filters() {
+        assert!(format!("filters:quality/{URL}")
+            .parse::<ImageProcessingPath>()
+            .is_err());
+        assert!(format!("filters:quality(80/{URL}")
+            .parse::<ImageProcessingPath>()
+            .is_err());
+    }
+
+    #[test]
+    fn test_parse_no_image_url() {
+        assert!("".parse::<ImageProcessingPath>().is_err());
+        assert!("100x100/".parse::<ImageProcessingPath>().is_err());
+    }
+}
@@ -1,6 +1,8 @@
 use serde::*;
 
+use crate::infra::image_encoding::OutputOptions;
 use crate::infra::image_manipulation;
+use crate::infra::smart_crop::FocalPoint;
 
 #[derive(Serialize, Deserialize)]
 pub struct Standard {
@@ -19,6 +21,24 @@ impl Standard {
 pub struct MetadataResponse {
     pub source: Source,
     pub operations: Vec<Operation>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub focal_point: Option<Point>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quality: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lossless: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub strip_exif: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub strip_icc: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub progressive: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub optimise: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_bytes: Option<u32>,
 }
 
 impl MetadataResponse {
@@ -27,20 +47,89 @@ impl MetadataResponse {
             .0
             .iter()
             .map(|op| match *op {
-                image_manipulation::Operation::Resize { width, height } => Operation {
+                image_manipulation::Operation::Trim { from, tolerance } => Operation {
+                    r#type: "trim".to_string(),
+                    from: Some(from.name().to_string()),
+                    tolerance: Some(tolerance),
+                    ..Default::default()
+                },
+                image_manipulation::Operation::Crop {
+                    left,
+                    top,
+                    right,
+                    bottom,
+                } => Operation {
+                    r#type: "crop".to_string(),
+                    left: Some(left),
+                    top: Some(top),
+                    right: Some(right),
+                    bottom: Some(bottom),
+                    ..Default::default()
+                },
+                image_manipulation::Operation::Resize {
+                    width,
+                    height,
+                    mode,
+                    horizontal_align,
+                    vertical_align,
+                    focal,
+                    smart,
+                } => Operation {
                     r#type: "resize".to_string(),
                     width: Some(width),
                     height: Some(height),
+                    mode: Some(mode.name().to_string()),
+                    horizontal_align: Some(horizontal_align.name().to_string()),
+                    vertical_align: Some(vertical_align.name().to_string()),
+                    focal: focal.map(|f| Region {
+                        left: f.left,
+                        top: f.top,
+                        right: f.right,
+                        bottom: f.bottom,
+                    }),
+                    smart: smart.then_some(true),
+                    ..Default::default()
                 },
                 image_manipulation::Operation::FlipHorizontally => Operation {
                     r#type: "flip_horizontally".to_string(),
-                    width: None,
-                    height: None,
+                    ..Default::default()
                 },
                 image_manipulation::Operation::FlipVertically => Operation {
                     r#type: "flip_vertically".to_string(),
-                    width: None,
-                    height: None,
+                    ..Default::default()
+                },
+                image_manipulation::Operation::Rotate90 => rotate("90", None),
+                image_manipulation::Operation::Rotate180 => rotate("180", None),
+                image_manipulation::Operation::Rotate270 => rotate("270", None),
+                image_manipulation::Operation::Rotate { degrees, fill } => {
+                    rotate(&degrees.to_string(), Some(fill))
+                }
+                image_manipulation::Operation::Pad {
+                    width,
+                    height,
+                    adaptive,
+                    horizontal_align,
+                    vertical_align,
+                    fill,
+                } => Operation {
+                    r#type: "pad".to_string(),
+                    width: Some(width),
+                    height: Some(height),
+                    adaptive: adaptive.then_some(true),
+                    horizontal_align: Some(horizontal_align.name().to_string()),
+                    vertical_align: Some(vertical_align.name().to_string()),
+                    fill: Some(fill.name()),
+                    ..Default::default()
+                },
+                image_manipulation::Operation::NoAutorotate => Operation {
+                    r#type: "no_autorotate".to_string(),
+                    ..Default::default()
+                },
+                image_manipulation::Operation::Filter(ref filter) => Operation {
+                    r#type: "filter".to_string(),
+                    name: Some(filter.name().to_string()),
+                    args: Some(filter.args()),
+                    ..Default::default()
                 },
             })
             .collect();
@@ -50,27 +139,116 @@ impl MetadataResponse {
                 url: url.to_string(),
             },
             operations,
+            focal_point: None,
+            format: None,
+            quality: None,
+            lossless: None,
+            strip_exif: None,
+            strip_icc: None,
+            progressive: None,
+            optimise: None,
+            max_bytes: None,
+        }
+    }
+
+    /// Adds the focal point that smart cropping picked (in source image pixels)
+    pub fn with_focal_point(self, focal_point: Option<FocalPoint>) -> Self {
+        MetadataResponse {
+            focal_point: focal_point.map(|p| Point { x: p.x, y: p.y }),
+            ..self
+        }
+    }
+
+    /// Adds how the processed image will be written out, when that was asked for
+    pub fn with_output(self, output: &OutputOptions) -> Self {
+        MetadataResponse {
+            format: output.format.map(|f| f.name().to_string()),
+            quality: output.quality,
+            lossless: output.lossless,
+            strip_exif: output.strip_exif.then_some(true),
+            strip_icc: output.strip_icc.then_some(true),
+            progressive: output.progressive,
+            optimise: output.optimise,
+            max_bytes: output.max_bytes,
+            ..self
         }
     }
 }
 
+fn rotate(degrees: &str, fill: Option<image_manipulation::Fill>) -> Operation {
+    Operation {
+        r#type: "rotate".to_string(),
+        degrees: Some(degrees.to_string()),
+        fill: fill.map(|fill| fill.name()),
+        ..Default::default()
+    }
+}
+
 #[derive(Serialize, Deserialize, Eq, PartialEq, Debug)]
 pub struct Source {
     pub url: String,
 }
 
-#[derive(Serialize, Deserialize, Eq, PartialEq, Debug)]
+#[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Default)]
 pub struct Operation {
     pub r#type: String,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub from: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tolerance: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub width: Option<u32>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub height: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mode: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub horizontal_align: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vertical_align: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub focal: Option<Region>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub smart: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub left: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub right: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bottom: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub degrees: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub adaptive: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fill: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub args: Option<Vec<String>>,
+}
+
+#[derive(Serialize, Deserialize, Eq, PartialEq, Debug)]
+pub struct Region {
+    pub left: u32,
+    pub top: u32,
+    pub right: u32,
+    pub bottom: u32,
+}
+
+#[derive(Serialize, Deserialize, Eq, PartialEq, Debug)]
+pub struct Point {
+    pub x: u32,
+    pub y: u32,
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::infra::filters::Filter;
     use crate::infra::image_caching::ImageResize;
+    use crate::infra::image_encoding::OutputFormat;
 
     use super::*;
 
@@ -90,19 +268,155 @@ mod tests {
                     r#type: "resize".to_string(),
                     width: Some(100),
                     height: Some(300),
+                    mode: Some("cover".to_string()),
+                    horizontal_align: Some("center".to_string()),
+                    vertical_align: Some("middle".to_string()),
+                    ..Default::default()
                 },
                 Operation {
                     r#type: "flip_horizontally".to_string(),
-                    width: None,
-                    height: None,
+                    ..Default::default()
                 },
                 Operation {
                     r#type: "flip_vertically".to_string(),
-                    width: None,
-                    height: None,
+                    ..Default::default()
                 },
             ],
+            focal_point: None,
+            format: None,
+            quality: None,
+            lossless: None,
+            strip_exif: None,
+            strip_icc: None,
+            progressive: None,
+            optimise: None,
+            max_bytes: None,
         };
         assert_eq!(expected, result)
     }
+
+    #[test]
+    fn test_metadata_response_build_with_focal() {
+        let domain = image_manipulation::Operations::build_with_options(
+            &Some(ImageResize {
+                target_width: 100,
+                target_height: 300,
+            }),
+            image_manipulation::ResizeOptions {
+                focal: Some(image_manipulation::FocalRegion {
+                    left: 1,
+                    top: 2,
+                    right: 3,
+                    bottom: 4,
+                }),
+                ..Default::default()
+            },
+        );
+        let result = MetadataResponse::build("http://beachape.com/images/lol.png", &domain);
+        assert_eq!(
+            Some(Region {
+                left: 1,
+                top: 2,
+                right: 3,
+                bottom: 4
+            }),
+            result.operations[0].focal
+        );
+    }
+
+    #[test]
+    fn test_metadata_response_build_with_crop() {
+        let domain = image_manipulation::Operations(vec![image_manipulation::Operation::Crop {
+            left: 1,
+            top: 2,
+            right: 3,
+            bottom: 4,
+        }]);
+        let result = MetadataResponse::build("http://beachape.com/images/lol.png", &domain);
+        assert_eq!(
+            vec![Operation {
+                r#type: "crop".to_string(),
+                left: Some(1),
+                top: Some(2),
+                right: Some(3),
+                bottom: Some(4),
+                ..Default::default()
+            }],
+            result.operations
+        );
+    }
+
+    #[test]
+    fn test_metadata_response_build_with_smart_focal_point() {
+        let domain = image_manipulation::Operations::build_with_options(
+            &Some(ImageResize {
+                target_width: 100,
+                target_height: 300,
+            }),
+            image_manipulation::ResizeOptions {
+                smart: true,
+                ..Default::default()
+            },
+        );
+        let result = MetadataResponse::build("http://beachape.com/images/lol.png", &domain)
+            .with_focal_point(Some(FocalPoint { x: 10, y: 20 }));
+        assert_eq!(Some(true), result.operations[0].smart);
+        assert_eq!(Some(Point { x: 10, y: 20 }), result.focal_point);
+    }
+
+    #[test]
+    fn test_metadata_response_build_with_trim() {
+        let domain = image_manipulation::Operations(vec![image_manipulation::Operation::Trim {
+            from: image_manipulation::TrimFrom::BottomRight,
+            tolerance: 10,
+        }]);
+        let result = MetadataResponse::build("http://beachape.com/images/lol.png", &domain);
+        assert_eq!(
+            vec![Operation {
+                r#type: "trim".to_string(),
+                from: Some("bottom-right".to_string()),
+                tolerance: Some(10),
+                ..Default::default()
+            }],
+            result.operations
+        );
+    }
+
+    #[test]
+    fn test_metadata_response_build_with_filter() {
+        let domain = image_manipulation::Operations(vec![image_manipulation::Operation::Filter(
+            Filter::Grayscale,
+        )]);
+        let result = MetadataResponse::build("http://beachape.com/images/lol.png", &domain);
+        assert_eq!(
+            vec![Operation {
+                r#type: "filter".to_string(),
+                name: Some("grayscale".to_string()),
+                args: Some(Vec::new()),
+                ..Default::default()
+            }],
+            result.operations
+        );
+    }
+
+    #[test]
+    fn test_metadata_response_build_with_output() {
+        let domain = image_manipulation::Operations::build(&None);
+        let result = MetadataResponse::build("http://beachape.com/images/lol.png", &domain)
+            .with_output(&OutputOptions {
+                format: Some(OutputFormat::WebP),
+                quality: Some(50),
+                lossless: Some(true),
+                strip_exif: true,
+                progressive: Some(true),
+                ..Default::default()
+            });
+        assert_eq!(Some("webp".to_string()), result.format);
+        assert_eq!(Some(50), result.quality);
+        assert_eq!(Some(true), result.lossless);
+        assert_eq!(Some(true), result.strip_exif);
+        assert_eq!(None, result.strip_icc);
+        assert_eq!(Some(true), result.progressive);
+        assert_eq!(None, result.optimise);
+    }
 }
@@ -1,5 +1,5 @@
 use std::any::Any;
-use std::io::Cursor;
+use std::io::{BufRead, Cursor, Seek};
 
 use axum::extract::{Path, State};
 use axum::http::header::ACCEPT;
@@ -8,22 +8,32 @@ use axum::response::{Html, IntoResponse, Response};
 use axum::{response::Json, routing::*, Router};
 
 use bytesize::ByteSize;
-use image::{ImageFormat, ImageReader};
-use reqwest::header::{CACHE_CONTROL, CONTENT_LENGTH, CONTENT_TYPE};
+use image::metadata::Orientation;
+use image::{DynamicImage, Frame, ImageDecoder, ImageError, ImageFormat, ImageReader};
+use reqwest::header::{CACHE_CONTROL, CONTENT_LENGTH, CONTENT_TYPE, VARY};
 use responses::Standard;
 use tower_http::catch_panic::CatchPanicLayer;
 use tracing::instrument;
 
-use crate::api::requests::{ImageResizePathParam, Signature};
+use crate::api::requests::{ImageProcessingPath, Signature};
 use crate::api::responses::{self, MetadataResponse};
+use crate::infra::animation::{decode_frames, encode_frames, supports_animation};
+use crate::infra::colour_profiles::convert_to_srgb;
 use crate::infra::components::AppComponents;
-use crate::infra::config::AuthenticationSettings;
+use crate::infra::config::{AuthenticationSettings, IccHandling, OutputSettings};
 use crate::infra::errors::AppError;
 use crate::infra::image_caching::{
     ImageCacher, ImageFetchRequest, ImageFetchedCacheRequest, ImageResizeRequest,
     ImageResizedCacheRequest,
 };
-use crate::infra::image_manipulation::{Operations, OperationsRunner, SingletonOperationsRunner};
+use crate::infra::image_encoding::{
+    encode_with_metadata, encode_within, negotiate_format, EncoderSettings, ImageMetadata,
+};
+use crate::infra::image_manipulation::{
+    parse_processing_path, scale_down, scale_down_frames, smart_focal_point, Operations,
+    OperationsRunner, SingletonOperationsRunner,
+};
+use crate::infra::svg::{is_svg, node_count, parse_svg, render_scale, render_size, render_svg};
 use crate::infra::validations::{SingletonValidator, Validator};
 
 use miniaturs_shared::signature::{ensure_signature_is_valid_for_path_and_query, SignatureError};
@@ -34,8 +44,8 @@ pub fn create_router(app_components: AppComponents) -> Router {
     Router::new()
         .route("/", get(root))
         .route("/health", get(health_check))
-        .route("/:signature/:resized_image/*image_url", get(resize))
-        .route("/:signature/meta/:resized_image/*image_url", get(metadata))
+        .route("/:signature/*processing_path", get(resize))
+        .route("/:signature/meta/*processing_path", get(metadata))
         .fallback(handle_404)
         .layer(CatchPanicLayer::custom(handle_panic))
         .with_state(app_components)
@@ -51,7 +61,8 @@ async fn root() -> Json<Standard> {
 async fn resize(
     State(app_components): State<AppComponents>,
     uri: Uri,
-    Path((signature, resized_image, image_url)): Path<(Signature, ImageResizePathParam, String)>,
+    request_headers: HeaderMap,
+    Path((signature, processing_path)): Path<(Signature, ImageProcessingPath)>,
 ) -> Result<Response, AppError> {
     ensure_signature_is_valid(
         &app_components.config.authentication_settings,
@@ -59,12 +70,28 @@ async fn resize(
         signature,
     )?;
     let validation_settings = &app_components.config.validation_settings;
-    let operations = Operations::build(&Some(resized_image.into()));
+    let output_settings = &app_components.config.output_settings;
+    let (operations, mut output) = parse_processing_path(&processing_path)?;
     SingletonValidator.validate_operations(validation_settings, &operations)?;
-    let processed_image_request = {
+    SingletonValidator.validate_output(validation_settings, &output)?;
+    // An explicitly requested format always wins, otherwise the response depends on Accept
+    let varies_by_accept =
+        output.format.is_none() && (output_settings.auto_webp || output_settings.auto_avif);
+    let accept = request_headers
+        .get(ACCEPT)
+        .and_then(|h| h.to_str().ok())
+        .unwrap_or_default();
+    if varies_by_accept {
+        output.format = negotiate_format(output_settings, accept);
+    }
+    let image_url = processing_path.image_url;
+    let mut processed_image_request = {
         ImageResizeRequest {
             requested_image_url: image_url.clone(),
             operations,
+            output,
+            encoder: EncoderSettings::resolve(&output, output_settings),
+            icc_handling: output_settings.icc_handling,
         }
     };
     let maybe_cached_resized_image = app_components
@@ -72,8 +99,12 @@ async fn resize(
         .get(&processed_image_request)
         .await?;
 
+    let mut response_headers = standard_headers();
+    if varies_by_accept {
+        response_headers.insert(VARY, HeaderValue::from_static("Accept"));
+    }
+
     if let Some(cached_resized_image) = maybe_cached_resized_image {
-        let mut response_headers = standard_headers();
         if let Ok(content_type_header) =
             HeaderValue::from_str(&cached_resized_image.requested.content_type)
         {
@@ -81,25 +112,193 @@ async fn resize(
         }
         Ok((StatusCode::OK, response_headers, cached_resized_image.bytes).into_response())
     } else {
-        let unprocessed_cache_retrieve_req = ImageFetchRequest {
-            requested_image_url: image_url.clone(),
+        let FetchedImage {
+            status_code: response_status_code,
+            image: original_image,
+            format: source_format,
+            frames,
+            metadata,
+            operations,
+        } = fetch_source_image(
+            &app_components,
+            &image_url,
+            &processed_image_request.operations,
+        )
+        .await?;
+        SingletonValidator.validate_operations_against_source_image(
+            validation_settings,
+            &operations,
+            &original_image,
+        )?;
+
+        let image = SingletonOperationsRunner
+            .run(original_image, &operations)
+            .await;
+
+        let mut format = processed_image_request.output.image_format(source_format);
+        let mut negotiated_request = None;
+        if varies_by_accept
+            && SingletonValidator
+                .validate_encoding(validation_settings, format, &image)
+                .is_err()
+        {
+            // Unlike an explicitly requested one, a negotiated format can fall back to
+            // something else the client accepts
+            let without_avif = OutputSettings {
+                auto_avif: false,
+                ..output_settings.clone()
+            };
+            // Cached under the format it actually comes out in, and under the one that was
+            // negotiated too, as that's what later requests for it look up
+            negotiated_request = Some(processed_image_request.clone());
+            processed_image_request.output.format = negotiate_format(&without_avif, accept);
+            format = processed_image_request.output.image_format(source_format);
+        }
+        SingletonValidator.validate_encoding(validation_settings, format, &image)?;
+        let encoder_settings = processed_image_request.encoder;
+        // The first frame alone decides the format, and gets checked, so animations follow suit
+        let processed_frames = match frames {
+            Some(frames) if supports_animation(format) => Some(
+                SingletonOperationsRunner
+                    .run_frames(frames, &operations)
+                    .await,
+            ),
+            _ => None,
+        };
+        let encode = |settings: &EncoderSettings, scale: f32| match &processed_frames {
+            Some(frames) if scale < 1.0 => {
+                encode_frames(scale_down_frames(frames, scale), format, settings)
+            }
+            Some(frames) => encode_frames(frames.clone(), format, settings),
+            None if scale < 1.0 => {
+                encode_with_metadata(&scale_down(&image, scale), format, settings, &metadata)
+            }
+            None => encode_with_metadata(&image, format, settings, &metadata),
+        };
+        let written_bytes = match processed_image_request.output.max_bytes {
+            Some(max_bytes) => encode_within(
+                max_bytes as usize,
+                validation_settings.max_bytes_attempts,
+                format,
+                &encoder_settings,
+                encode,
+            )?,
+            None => encode(&encoder_settings, 1.0)?,
         };
+        SingletonValidator
+            .validate_encoded_size(&processed_image_request.output, written_bytes.len())?;
 
-        let maybe_cached_fetched_image = app_components
-            .unprocessed_images_cacher
-            .get(&unprocessed_cache_retrieve_req)
+        let cache_image_req = ImageResizedCacheRequest {
+            request: processed_image_request,
+            content_type: format.to_mime_type().to_string(),
+        };
+
+        //cache the thing
+        app_components
+            .processed_images_cacher
+            .set(&written_bytes, &cache_image_req)
             .await?;
+        if let Some(request) = negotiated_request {
+            let negotiated_cache_image_req = ImageResizedCacheRequest {
+                request,
+                content_type: cache_image_req.content_type.clone(),
+            };
+            app_components
+                .processed_images_cacher
+                .set(&written_bytes, &negotiated_cache_image_req)
+                .await?;
+        }
 
-        let (response_status_code, bytes, maybe_content_type_string) = if let Some(cached_fetched) =
-            maybe_cached_fetched_image
-        {
+        if let Ok(content_type_header) = HeaderValue::from_str(&cache_image_req.content_type) {
+            response_headers.insert(CONTENT_TYPE, content_type_header);
+        }
+
+        Ok((response_status_code, response_headers, written_bytes).into_response())
+    }
+}
+
+#[instrument(skip(app_components))]
+async fn metadata(
+    State(app_components): State<AppComponents>,
+    uri: Uri,
+    Path((signature, processing_path)): Path<(Signature, ImageProcessingPath)>,
+) -> Result<Response, AppError> {
+    ensure_signature_is_valid(
+        &app_components.config.authentication_settings,
+        &uri,
+        signature,
+    )?;
+
+    let (operations, output) = parse_processing_path(&processing_path)?;
+    let mut response_headers = HeaderMap::new();
+    response_headers.insert(CACHE_CONTROL, CACHE_CONTROL_HEADER_VALUE);
+
+    SingletonValidator
+        .validate_operations(&app_components.config.validation_settings, &operations)?;
+    SingletonValidator.validate_output(&app_components.config.validation_settings, &output)?;
+
+    // Only smart cropping needs to look at the image itself
+    let focal_point = if operations.has_smart_resize() {
+        let FetchedImage {
+            image,
+            operations: source_operations,
+            ..
+        } = fetch_source_image(&app_components, &processing_path.image_url, &operations).await?;
+        SingletonValidator.validate_operations_against_source_image(
+            &app_components.config.validation_settings,
+            &source_operations,
+            &image,
+        )?;
+        smart_focal_point(&image, &source_operations)
+    } else {
+        None
+    };
+
+    let metadata = MetadataResponse::build(&processing_path.image_url, &operations)
+        .with_focal_point(focal_point)
+        .with_output(&output);
+    Ok((StatusCode::OK, response_headers, Json(metadata)).into_response())
+}
+
+struct FetchedImage {
+    status_code: StatusCode,
+    // The first frame, for animations
+    image: DynamicImage,
+    format: ImageFormat,
+    // Only there for animations
+    frames: Option<Vec<Frame>>,
+    metadata: ImageMetadata,
+    // The operations to run on the image, which only differ from the requested ones for sources
+    // that got rendered at a different scale (SVGs)
+    operations: Operations,
+}
+
+// Gets the source image from the unprocessed cache, or failing that, from the remote url
+// (caching it on the way), then decodes (or for SVGs, renders) and validates it.
+async fn fetch_source_image(
+    app_components: &AppComponents,
+    image_url: &str,
+    operations: &Operations,
+) -> Result<FetchedImage, AppError> {
+    let validation_settings = &app_components.config.validation_settings;
+    let unprocessed_cache_retrieve_req = ImageFetchRequest {
+        requested_image_url: image_url.to_string(),
+    };
+
+    let maybe_cached_fetched_image = app_components
+        .unprocessed_images_cacher
+        .get(&unprocessed_cache_retrieve_req)
+        .await?;
+
+    let (response_status_code, bytes, maybe_content_type_string) =
+        if let Some(cached_fetched) = maybe_cached_fetched_image {
             (
                 StatusCode::OK,
                 cached_fetched.bytes,
                 cached_fetched.requested.content_type,
             )
         } else {
-            let mut proxy_response = app_components.http_client.get(&image_url).send().await?;
+            let mut proxy_response = app_components.http_client.get(image_url).send().await?;
             let status_code = proxy_response.status();
             let headers = proxy_response.headers_mut();
 
@@ -134,76 +333,149 @@ async fn resize(
             (response_status_code, bytes, maybe_content_type_string)
         };
 
-        let mut image_reader = ImageReader::new(Cursor::new(bytes));
+    // SVGs get rendered at whatever size the operations need, rather than decoded
+    if is_svg(&bytes, maybe_content_type_string.as_deref(), image_url) {
+        let tree = parse_svg(&bytes)?;
+        let scale = render_scale(&tree, operations);
+        SingletonValidator.validate_svg(
+            validation_settings,
+            node_count(&tree),
+            render_size(&tree, scale),
+        )?;
+        let image =
+            render_svg(&tree, scale).ok_or_else(|| anyhow::anyhow!("Could not render SVG"))?;
+        SingletonValidator.validate_source_image(validation_settings, &image)?;
+        return Ok(FetchedImage {
+            status_code: response_status_code,
+            image,
+            // Unless asked for something else, there has to be some raster format to write out
+            format: ImageFormat::Png,
+            frames: None,
+            metadata: ImageMetadata::default(),
+            operations: operations.scaled(scale),
+        });
+    }
 
-        let maybe_image_format_from_input = maybe_content_type_string
-            .as_ref()
-            .and_then(ImageFormat::from_mime_type)
-            .or_else(|| ImageFormat::from_path(image_url).ok());
+    let to_srgb = app_components.config.output_settings.icc_handling == IccHandling::Convert;
+
+    #[cfg(feature = "heic")]
+    if crate::infra::heic::is_heic(&bytes, maybe_content_type_string.as_deref(), image_url) {
+        let (image, metadata) = crate::infra::heic::decode_heic(&bytes, operations.autorotates())?;
+        let (image, metadata) = manage_colours(image, metadata, to_srgb);
+        SingletonValidator.validate_source_image(validation_settings, &image)?;
+        return Ok(FetchedImage {
+            status_code: response_status_code,
+            image,
+            // Unless asked for something else, HEIC photos are written out as JPEG
+            format: ImageFormat::Jpeg,
+            frames: None,
+            metadata,
+            operations: operations.clone(),
+        });
+    }
 
-        let reader_with_format = if let Some(image_format) = maybe_image_format_from_input {
-            image_reader.set_format(image_format);
-            image_reader
+    #[cfg(feature = "jxl")]
+    if crate::infra::jxl::is_jxl(&bytes, maybe_content_type_string.as_deref(), image_url) {
+        let (image, metadata) = crate::infra::jxl::decode_jxl(&bytes, operations.autorotates())?;
+        let (image, metadata) = manage_colours(image, metadata, to_srgb);
+        SingletonValidator.validate_source_image(validation_settings, &image)?;
+        // There's no JPEG XL encoder, so unless asked for something else these are written out as
+        // JPEG, or PNG if there's transparency to keep
+        let format = if image.color().has_alpha() {
+            ImageFormat::Png
         } else {
-            image_reader.with_guessed_format()?
+            ImageFormat::Jpeg
         };
+        return Ok(FetchedImage {
+            status_code: response_status_code,
+            image,
+            format,
+            frames: None,
+            metadata,
+            operations: operations.clone(),
+        });
+    }
 
-        let format = reader_with_format
-            .format()
-            .ok_or(AppError::UnableToDetermineFormat)?;
+    let mut image_reader = ImageReader::new(Cursor::new(&bytes));
 
-        let original_image = reader_with_format.decode()?;
-        SingletonValidator.validate_source_image(validation_settings, &original_image)?;
+    let maybe_image_format_from_input = maybe_content_type_string
+        .as_ref()
+        .and_then(ImageFormat::from_mime_type)
+        .or_else(|| ImageFormat::from_path(image_url).ok());
 
-        let image = SingletonOperationsRunner
-            .run(original_image, &processed_image_request.operations)
-            .await;
+    let reader_with_format = if let Some(image_format) = maybe_image_format_from_input {
+        image_reader.set_format(image_format);
+        image_reader
+    } else {
+        image_reader.with_guessed_format()?
+    };
 
-        let mut cursor = Cursor::new(Vec::new());
-        image.write_to(&mut cursor, format)?;
-        let written_bytes = cursor.into_inner();
+    let format = reader_with_format
+        .format()
+        .ok_or(AppError::UnableToDetermineFormat)?;
 
-        let cache_image_req = ImageResizedCacheRequest {
-            request: processed_image_request,
-            content_type: format.to_mime_type().to_string(),
-        };
+    let (image, metadata) =
+        decode_source_image(reader_with_format, operations.autorotates(), to_srgb)?;
+    SingletonValidator.validate_source_image(validation_settings, &image)?;
 
-        //cache the thing
-        app_components
-            .processed_images_cacher
-            .set(&written_bytes, &cache_image_req)
-            .await?;
+    // One past the limit is enough to tell that there are too many
+    let frames = decode_frames(&bytes, format, validation_settings.max_frames as usize + 1)?;
+    if let Some(frames) = &frames {
+        SingletonValidator.validate_frame_count(validation_settings, frames.len())?;
+    }
 
-        let mut response_headers = standard_headers();
-        if let Ok(content_type_header) = HeaderValue::from_str(&cache_image_req.content_type) {
-            response_headers.insert(CONTENT_TYPE, content_type_header);
-        }
+    Ok(FetchedImage {
+        status_code: response_status_code,
+        image,
+        format,
+        frames,
+        metadata,
+        operations: operations.clone(),
+    })
+}
 
-        Ok((response_status_code, response_headers, written_bytes).into_response())
+// Decodes the image, turned the way its EXIF orientation says (if it has one) unless told not to,
+// and optionally converted to sRGB, so that operations see it the way it's meant to be looked at.
+// Comes with the metadata that's still relevant to the pixels.
+fn decode_source_image<R: BufRead + Seek>(
+    reader: ImageReader<R>,
+    autorotate: bool,
+    to_srgb: bool,
+) -> Result<(DynamicImage, ImageMetadata), ImageError> {
+    let mut decoder = reader.into_decoder()?;
+    let orientation = decoder.orientation()?;
+    let mut metadata = ImageMetadata {
+        icc_profile: decoder.icc_profile()?,
+        exif: decoder.exif_metadata()?,
+    };
+    let mut image = DynamicImage::from_decoder(decoder)?;
+    if autorotate {
+        image.apply_orientation(orientation);
+        // Otherwise anything reading the EXIF of the output would turn it all over again
+        if let Some(exif) = metadata.exif.as_mut() {
+            let _ = Orientation::remove_from_exif_chunk(exif);
+        }
     }
+    Ok(manage_colours(image, metadata, to_srgb))
 }
 
-#[instrument(skip(app_components))]
-async fn metadata(
-    State(app_components): State<AppComponents>,
-    uri: Uri,
-    Path((signature, resized_image, image_url)): Path<(Signature, ImageResizePathParam, String)>,
-) -> Result<Response, AppError> {
-    ensure_signature_is_valid(
-        &app_components.config.authentication_settings,
-        &uri,
-        signature,
-    )?;
-
-    let operations = Operations::build(&Some(resized_image.into()));
-    let mut response_headers = HeaderMap::new();
-    response_headers.insert(CACHE_CONTROL, CACHE_CONTROL_HEADER_VALUE);
-
-    SingletonValidator
-        .validate_operations(&app_components.config.validation_settings, &operations)?;
-
-    let metadata = MetadataResponse::build(&image_url, &operations);
-    Ok((StatusCode::OK, response_headers, Json(metadata)).into_response())
+// Converts the image to sRGB if asked to and it has a profile, in which case the profile goes
+fn manage_colours(
+    image: DynamicImage,
+    mut metadata: ImageMetadata,
+    to_srgb: bool,
+) -> (DynamicImage, ImageMetadata) {
+    match metadata.icc_profile.take() {
+        // Once converted, the profile no longer describes the pixels
+        Some(icc_profile) if to_srgb => (convert_to_srgb(image, &icc_profile), metadata),
+        icc_profile => (
+            image,
+            ImageMetadata {
+                icc_profile,
+                ..metadata
+            },
+        ),
+    }
 }
 
 #[instrument]
@@ -331,12 +603,21 @@ fn standard_headers() -> HeaderMap {
 
 #[cfg(test)]
 mod tests {
+    use aws_config::{BehaviorVersion, Region, SdkConfig};
+    use axum::body::Body;
+    use axum::http::Request;
+    use lambda_http::tower::ServiceExt;
     use miniaturs_shared::signature::make_url_safe_base64_hash;
 
     use super::*;
+    use crate::infra::config::{
+        AwsSettings, Config, ImageCacheSettings, OutputSettings, ValidationSettings,
+    };
+    use crate::infra::image_encoding::encode;
+    use crate::test_utils::TestResult;
     use std::str::FromStr;
 
-    const SECRET: &'static str = "doyouwanttoknowasecretdoyoupromisenottotellwhoaohoh";
+    const SECRET: &str = "doyouwanttoknowasecretdoyoupromisenottotellwhoaohoh";
 
     #[test]
     fn test_ensure_signature_is_valid_fails_if_sig_is_wrong() -> Result<(), AppError> {
@@ -360,7 +641,7 @@ mod tests {
 
         let url_string = "200x-100/https://beachape.com/images/octopress_with_container.png";
 
-        let generated_sig = make_url_safe_base64_hash(&SECRET, url_string)?;
+        let generated_sig = make_url_safe_base64_hash(SECRET, url_string)?;
 
         let signature = Signature(generated_sig.clone());
         let uri_with_signature_and_path = format!("http://test.com/{generated_sig}/{url_string}");
@@ -380,7 +661,7 @@ mod tests {
         let url_string =
             "200x-100/https://beachape.com/images/octopress_with_container.png?hello=world";
 
-        let generated_sig = make_url_safe_base64_hash(&SECRET, url_string)?;
+        let generated_sig = make_url_safe_base64_hash(SECRET, url_string)?;
 
         let signature = Signature(generated_sig.clone());
         let uri_with_signature_and_path = format!("http://test.com/{generated_sig}/{url_string}");
@@ -398,7 +679,7 @@ mod tests {
 
         let url_string = "200x-100/https://beachape.com/images/octopress_with_container.png";
 
-        let generated_sig = make_url_safe_base64_hash(&SECRET, url_string)?;
+        let generated_sig = make_url_safe_base64_hash(SECRET, url_string)?;
 
         let signature = Signature(generated_sig.clone());
         // Lambda + Axum
@@ -409,4 +690,173 @@ mod tests {
 
         ensure_signature_is_valid(&auth_settings, &uri, signature)
     }
+
+    #[test]
+    fn test_decode_source_image_autorotates() -> TestResult<()> {
+        // A 4x2 JPEG whose EXIF says it has to be turned 90 degrees clockwise (orientation 6)
+        let mut jpeg = encode(
+            &DynamicImage::new_rgb8(4, 2),
+            ImageFormat::Jpeg,
+            &EncoderSettings::default(),
+        )?;
+        let tiff: &[u8] = &[
+            b'M', b'M', 0, 42, 0, 0, 0, 8, // big endian header, first IFD right after
+            0, 1, // one entry
+            0x01, 0x12, 0, 3, 0, 0, 0, 1, 0, 6, 0, 0, // orientation, SHORT, 1 value: 6
+            0, 0, 0, 0, // no next IFD
+        ];
+        let mut app1 = vec![0xFF, 0xE1];
+        app1.extend_from_slice(&(2 + 6 + tiff.len() as u16).to_be_bytes());
+        app1.extend_from_slice(b"Exif\0\0");
+        app1.extend_from_slice(tiff);
+        jpeg.splice(2..2, app1);
+
+        let decode = |autorotate| {
+            let mut reader = ImageReader::new(Cursor::new(&jpeg));
+            reader.set_format(ImageFormat::Jpeg);
+            decode_source_image(reader, autorotate, true)
+        };
+        let (rotated, metadata) = decode(true)?;
+        assert_eq!((2, 4), (rotated.width(), rotated.height()));
+        // The orientation is cleared from the EXIF that may be carried over to the output
+        let exif = metadata.exif.unwrap();
+        assert_eq!(
+            Some(Orientation::NoTransforms),
+            Orientation::from_exif_chunk(&exif)
+        );
+        let (as_stored, metadata) = decode(false)?;
+        assert_eq!((4, 2), (as_stored.width(), as_stored.height()));
+        let exif = metadata.exif.unwrap();
+        assert_eq!(
+            Some(Orientation::Rotate90),
+            Orientation::from_exif_chunk(&exif)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_decode_source_image_converts_to_srgb() -> TestResult<()> {
+        let display_p3 = moxcms::ColorProfile::new_display_p3().encode()?;
+        let png = encode_with_metadata(
+            &DynamicImage::ImageRgb8(image::RgbImage::from_pixel(
+                2,
+                2,
+                image::Rgb([200, 100, 50]),
+            )),
+            ImageFormat::Png,
+            &EncoderSettings::default(),
+            &ImageMetadata {
+                icc_profile: Some(display_p3.clone()),
+                exif: None,
+            },
+        )?;
+        let decode = |to_srgb| {
+            let mut reader = ImageReader::new(Cursor::new(&png));
+            reader.set_format(ImageFormat::Png);
+            decode_source_image(reader, true, to_srgb)
+        };
+
+        let (converted, metadata) = decode(true)?;
+        assert_ne!(
+            &image::Rgb([200, 100, 50]),
+            converted.to_rgb8().get_pixel(0, 0)
+        );
+        assert_eq!(None, metadata.icc_profile);
+
+        let (untouched, metadata) = decode(false)?;
+        assert_eq!(
+            &image::Rgb([200, 100, 50]),
+            untouched.to_rgb8().get_pixel(0, 0)
+        );
+        assert_eq!(Some(display_p3), metadata.icc_profile);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_router_routes_thumbor_paths() -> TestResult<()> {
+        let router = create_router(app_components()?);
+        let url = "https://beachape.com/images/octopress_with_container.png";
+        let signed = |path: String| -> TestResult<String> {
+            let hash = make_url_safe_base64_hash(SECRET, &path)?;
+            Ok(format!("/{hash}/{path}"))
+        };
+        let full =
+            "trim:bottom-right:5/1x2:3x4/fit-in/200x-100/left/top/filters:quality(80):fill(blur)";
+        // Metadata is worked out without fetching the image (as none of them are smart), while
+        // resizes that are too large get rejected before they get that far
+        let cases = [
+            (
+                signed(format!("meta/200x-100/{url}"))?,
+                StatusCode::OK,
+                "\"width\":200",
+            ),
+            (signed(format!("meta/{url}"))?, StatusCode::OK, url),
+            (
+                signed(format!("meta/{full}/{url}"))?,
+                StatusCode::OK,
+                "\"quality\":80",
+            ),
+            (
+                signed(format!("20000x-100/{url}"))?,
+                StatusCode::BAD_REQUEST,
+                "Resize target width [20000] too large",
+            ),
+            (
+                signed(format!("{}/{url}", full.replace("200x-100", "200x-20000")))?,
+                StatusCode::BAD_REQUEST,
+                "Resize target height [20000] too large",
+            ),
+            (
+                format!("/lol/{url}"),
+                StatusCode::UNAUTHORIZED,
+                "[lol] was not correct",
+            ),
+            (
+                "/lol/me.png".to_string(),
+                StatusCode::UNAUTHORIZED,
+                "[lol] was not correct",
+            ),
+        ];
+        for (path, status, expected_body) in cases {
+            let response = router
+                .clone()
+                .oneshot(Request::builder().uri(&path).body(Body::empty())?)
+                .await?;
+            assert_eq!(status, response.status(), "{path}");
+            assert_eq!(
+                Some("application/json"),
+                response
+                    .headers()
+                    .get(CONTENT_TYPE)
+                    .and_then(|h| h.to_str().ok()),
+                "{path}"
+            );
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await?;
+            let body = String::from_utf8(body.to_vec())?;
+            assert!(body.contains(expected_body), "{path}: {body}");
+        }
+        Ok(())
+    }
+
+    fn app_components() -> Result<AppComponents, anyhow::Error> {
+        let aws_config = SdkConfig::builder()
+            .behavior_version(BehaviorVersion::latest())
+            .region(Region::new("us-east-1"))
+            .build();
+        AppComponents::create(Config {
+            authentication_settings: AuthenticationSettings {
+                shared_secret: SECRET.to_string(),
+            },
+            image_cache_settings: ImageCacheSettings {
+                processed_images_bucket_name: "processed".to_string(),
+                unprocessed_images_bucket_name: "unprocessed".to_string(),
+            },
+            aws_settings: AwsSettings {
+                aws_config,
+                path_style_s3: true,
+            },
+            validation_settings: ValidationSettings::default(),
+            output_settings: OutputSettings::default(),
+        })
+    }
 }
@@ -0,0 +1,182 @@
+use std::io::Cursor;
+
+use image::codecs::gif::{GifDecoder, GifEncoder, Repeat};
+use image::codecs::webp::WebPDecoder;
+use image::error::{EncodingError, ImageFormatHint};
+use image::{AnimationDecoder, DynamicImage, Frame, ImageError, ImageFormat};
+
+use super::image_encoding::{encode, EncoderSettings};
+
+/// Decodes up to `limit` frames of an animated GIF or WebP, or returns None if the image isn't
+/// an animation (or is in a format that can't be one)
+pub fn decode_frames(
+    bytes: &[u8],
+    format: ImageFormat,
+    limit: usize,
+) -> Result<Option<Vec<Frame>>, ImageError> {
+    let frames = match format {
+        ImageFormat::Gif => GifDecoder::new(Cursor::new(bytes))?
+            .into_frames()
+            .take(limit)
+            .collect::<Result<Vec<_>, _>>()?,
+        ImageFormat::WebP => {
+            let decoder = WebPDecoder::new(Cursor::new(bytes))?;
+            if !decoder.has_animation() {
+                return Ok(None);
+            }
+            decoder
+                .into_frames()
+                .take(limit)
+                .collect::<Result<Vec<_>, _>>()?
+        }
+        _ => return Ok(None),
+    };
+    // A single frame GIF is just a still image
+    Ok((frames.len() > 1).then_some(frames))
+}
+
+/// Whether frames can be written out as an animation in this format, rather than just the first one
+pub fn supports_animation(format: ImageFormat) -> bool {
+    matches!(format, ImageFormat::Gif | ImageFormat::WebP)
+}
+
+/// Writes the frames out as a looping animation. Formats that can't animate get the first frame.
+pub fn encode_frames(
+    frames: Vec<Frame>,
+    format: ImageFormat,
+    settings: &EncoderSettings,
+) -> Result<Vec<u8>, ImageError> {
+    match format {
+        ImageFormat::Gif => {
+            let mut cursor = Cursor::new(Vec::new());
+            {
+                let mut encoder = GifEncoder::new(&mut cursor);
+                encoder.set_repeat(Repeat::Infinite)?;
+                encoder.encode_frames(frames)?;
+            }
+            Ok(cursor.into_inner())
+        }
+        ImageFormat::WebP => encode_webp_frames(&frames, settings),
+        _ => match frames.into_iter().next() {
+            Some(frame) => encode(
+                &DynamicImage::ImageRgba8(frame.into_buffer()),
+                format,
+                settings,
+            ),
+            None => Err(encoding_error(format, "No frames to encode")),
+        },
+    }
+}
+
+fn encode_webp_frames(frames: &[Frame], settings: &EncoderSettings) -> Result<Vec<u8>, ImageError> {
+    let (width, height) = frames
+        .first()
+        .map(|f| f.buffer().dimensions())
+        .ok_or_else(|| encoding_error(ImageFormat::WebP, "No frames to encode"))?;
+    let mut config = webp::WebPConfig::new()
+        .map_err(|_| encoding_error(ImageFormat::WebP, "Could not set up the WebP encoder"))?;
+    config.quality = settings.quality as f32;
+    config.lossless = settings.webp_lossless as i32;
+
+    let mut encoder = webp::AnimEncoder::new(width, height, &config);
+    // Loop forever, like GIFs do
+    encoder.set_loop_count(0);
+    let mut timestamp_ms = 0;
+    for frame in frames {
+        let buffer = frame.buffer();
+        encoder.add_frame(webp::AnimFrame::from_rgba(
+            buffer.as_raw(),
+            buffer.width(),
+            buffer.height(),
+            timestamp_ms,
+        ));
+        let (numerator, denominator) = frame.delay().numer_denom_ms();
+        timestamp_ms += (numerator / denominator.max(1)) as i32;
+    }
+    encoder
+        .try_encode()
+        .map(|memory| memory.to_vec())
+        .map_err(|e| encoding_error(ImageFormat::WebP, format!("{e:?}")))
+}
+
+fn encoding_error(format: ImageFormat, message: impl Into<String>) -> ImageError {
+    ImageError::Encoding(EncodingError::new(
+        ImageFormatHint::Exact(format),
+        message.into(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Delay, Rgba, RgbaImage};
+
+    use super::*;
+
+    fn frames() -> Vec<Frame> {
+        [[255, 0, 0, 255], [0, 255, 0, 255], [0, 0, 255, 255]]
+            .into_iter()
+            .map(|colour| {
+                Frame::from_parts(
+                    RgbaImage::from_pixel(8, 6, Rgba(colour)),
+                    0,
+                    0,
+                    Delay::from_numer_denom_ms(100, 1),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_gif_round_trip() {
+        let bytes = encode_frames(frames(), ImageFormat::Gif, &EncoderSettings::default()).unwrap();
+        let decoded = decode_frames(&bytes, ImageFormat::Gif, 10)
+            .unwrap()
+            .unwrap();
+        assert_eq!(3, decoded.len());
+        assert_eq!(Delay::from_numer_denom_ms(100, 1), decoded[1].delay());
+        assert_eq!(&Rgba([0, 255, 0, 255]), decoded[1].buffer().get_pixel(4, 3));
+
+        // Decoding stops at the limit
+        let limited = decode_frames(&bytes, ImageFormat::Gif, 2).unwrap().unwrap();
+        assert_eq!(2, limited.len());
+    }
+
+    #[test]
+    fn test_webp_round_trip() {
+        let settings = EncoderSettings {
+            webp_lossless: true,
+            ..Default::default()
+        };
+        let bytes = encode_frames(frames(), ImageFormat::WebP, &settings).unwrap();
+        let decoded = decode_frames(&bytes, ImageFormat::WebP, 10)
+            .unwrap()
+            .unwrap();
+        assert_eq!(3, decoded.len());
+        assert_eq!((8, 6), decoded[2].buffer().dimensions());
+        let Rgba([r, g, b, _]) = *decoded[2].buffer().get_pixel(4, 3);
+        assert!(b > 250 && r < 5 && g < 5, "{r} {g} {b}");
+    }
+
+    #[test]
+    fn test_still_images_are_not_animations() {
+        let still = encode(
+            &DynamicImage::new_rgba8(4, 4),
+            ImageFormat::Gif,
+            &EncoderSettings::default(),
+        )
+        .unwrap();
+        assert!(decode_frames(&still, ImageFormat::Gif, 10)
+            .unwrap()
+            .is_none());
+        assert!(decode_frames(&still, ImageFormat::Png, 10)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_encode_frames_in_still_format() {
+        let bytes = encode_frames(frames(), ImageFormat::Png, &EncoderSettings::default()).unwrap();
+        let decoded = image::load_from_memory_with_format(&bytes, ImageFormat::Png).unwrap();
+        assert_eq!(&Rgba([255, 0, 0, 255]), decoded.to_rgba8().get_pixel(0, 0));
+    }
+}
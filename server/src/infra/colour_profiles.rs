@@ -0,0 +1,105 @@
+use image::{DynamicImage, ImageBuffer};
+use moxcms::{ColorProfile, DataColorSpace, Layout, TransformOptions};
+
+/// Converts the pixels from the colour space described by the ICC profile to sRGB, which is what
+/// anything without colour management (and the output, once the profile is gone) assumes.
+///
+/// Images are left as they are if the profile can't be used, e.g. because it's broken or isn't
+/// an RGB one (CMYK sources are already turned into RGB when decoding).
+pub fn convert_to_srgb(image: DynamicImage, icc_profile: &[u8]) -> DynamicImage {
+    let source = match ColorProfile::new_from_slice(icc_profile) {
+        Ok(profile) if profile.color_space == DataColorSpace::Rgb => profile,
+        _ => return image,
+    };
+    let srgb = ColorProfile::new_srgb();
+    let has_alpha = image.color().has_alpha();
+    let layout = if has_alpha { Layout::Rgba } else { Layout::Rgb };
+
+    if image.color().bytes_per_pixel() / image.color().channel_count() > 1 {
+        // Keep the extra depth of 16-bit (and float) images through the conversion
+        let Ok(transform) =
+            source.create_transform_16bit(layout, &srgb, layout, TransformOptions::default())
+        else {
+            return image;
+        };
+        if has_alpha {
+            let pixels = image.to_rgba16();
+            let mut converted = ImageBuffer::new(pixels.width(), pixels.height());
+            match transform.transform(&pixels, &mut converted) {
+                Ok(()) => DynamicImage::ImageRgba16(converted),
+                Err(_) => image,
+            }
+        } else {
+            let pixels = image.to_rgb16();
+            let mut converted = ImageBuffer::new(pixels.width(), pixels.height());
+            match transform.transform(&pixels, &mut converted) {
+                Ok(()) => DynamicImage::ImageRgb16(converted),
+                Err(_) => image,
+            }
+        }
+    } else {
+        let Ok(transform) =
+            source.create_transform_8bit(layout, &srgb, layout, TransformOptions::default())
+        else {
+            return image;
+        };
+        if has_alpha {
+            let pixels = image.to_rgba8();
+            let mut converted = ImageBuffer::new(pixels.width(), pixels.height());
+            match transform.transform(&pixels, &mut converted) {
+                Ok(()) => DynamicImage::ImageRgba8(converted),
+                Err(_) => image,
+            }
+        } else {
+            let pixels = image.to_rgb8();
+            let mut converted = ImageBuffer::new(pixels.width(), pixels.height());
+            match transform.transform(&pixels, &mut converted) {
+                Ok(()) => DynamicImage::ImageRgb8(converted),
+                Err(_) => image,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgb, RgbImage};
+
+    use super::*;
+
+    #[test]
+    fn test_convert_display_p3_to_srgb() {
+        let display_p3 = ColorProfile::new_display_p3().encode().unwrap();
+        let image = DynamicImage::ImageRgb8(RgbImage::from_pixel(2, 2, Rgb([0, 255, 0])));
+        let converted = convert_to_srgb(image, &display_p3).to_rgb8();
+        // P3 green is beyond what sRGB can show, so it gets clipped to the sRGB primary
+        let Rgb([r, g, b]) = *converted.get_pixel(0, 0);
+        assert!(r < 10 && g > 245 && b < 10, "{r} {g} {b}");
+
+        // Colours inside both gamuts end up with different values
+        let image = DynamicImage::ImageRgb8(RgbImage::from_pixel(2, 2, Rgb([200, 100, 50])));
+        let converted = convert_to_srgb(image, &display_p3).to_rgb8();
+        assert_ne!(&Rgb([200, 100, 50]), converted.get_pixel(0, 0));
+    }
+
+    #[test]
+    fn test_convert_keeps_alpha_and_depth() {
+        let adobe_rgb = ColorProfile::new_adobe_rgb().encode().unwrap();
+        let image = DynamicImage::ImageRgba16(ImageBuffer::from_pixel(
+            2,
+            2,
+            image::Rgba([30_000, 20_000, 10_000, 1_234]),
+        ));
+        let converted = convert_to_srgb(image, &adobe_rgb);
+        let DynamicImage::ImageRgba16(pixels) = converted else {
+            panic!("Expected a 16-bit RGBA image, got {:?}", converted.color());
+        };
+        assert_eq!(1_234, pixels.get_pixel(0, 0)[3]);
+    }
+
+    #[test]
+    fn test_convert_leaves_image_alone_for_unusable_profiles() {
+        let image = DynamicImage::ImageRgb8(RgbImage::from_pixel(2, 2, Rgb([200, 100, 50])));
+        assert_eq!(image, convert_to_srgb(image.clone(), b"not a profile"));
+    }
+}
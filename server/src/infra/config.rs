@@ -1,11 +1,14 @@
 use std::{
     env::{self, VarError},
+    fmt::Display,
+    ops::RangeInclusive,
     str::FromStr,
 };
 
 use anyhow::Context;
 use aws_config::{BehaviorVersion, SdkConfig};
 use bytesize::ByteSize;
+use serde::{Deserialize, Serialize};
 use tracing::instrument;
 
 const SHARED_SECRET_ENV_KEY: &str = "MINIATURS_SHARED_SECRET";
@@ -18,6 +21,24 @@ const MAX_SOURCE_IMAGE_WIDTH: &str = "MAX_SOURCE_IMAGE_WIDTH";
 const MAX_SOURCE_IMAGE_HEIGHT: &str = "MAX_SOURCE_IMAGE_HEIGHT";
 const MAX_IMAGE_DOWNLOAD_SIZE_KEY: &str = "MAX_IMAGE_DOWNLOAD_SIZE";
 const MAX_IMAGE_FILE_SIZE_KEY: &str = "MAX_IMAGE_FILE_SIZE";
+const AUTO_WEBP_KEY: &str = "AUTO_WEBP";
+const AUTO_AVIF_KEY: &str = "AUTO_AVIF";
+const DEFAULT_QUALITY_KEY: &str = "DEFAULT_QUALITY";
+const AVIF_QUALITY_KEY: &str = "AVIF_QUALITY";
+const AVIF_SPEED_KEY: &str = "AVIF_SPEED";
+const WEBP_LOSSLESS_KEY: &str = "WEBP_LOSSLESS";
+const KEEP_EXIF_KEY: &str = "KEEP_EXIF";
+const KEEP_ICC_KEY: &str = "KEEP_ICC";
+const ICC_HANDLING_KEY: &str = "ICC_HANDLING";
+const PROGRESSIVE_JPEG_KEY: &str = "PROGRESSIVE_JPEG";
+const OPTIMISE_OUTPUT_KEY: &str = "OPTIMISE_OUTPUT";
+const MAX_AVIF_OUTPUT_PIXELS_KEY: &str = "MAX_AVIF_OUTPUT_PIXELS";
+const MAX_FRAMES_KEY: &str = "MAX_FRAMES";
+const MAX_SVG_NODES_KEY: &str = "MAX_SVG_NODES";
+const MAX_SVG_RENDER_PIXELS_KEY: &str = "MAX_SVG_RENDER_PIXELS";
+const MAX_BYTES_ATTEMPTS_KEY: &str = "MAX_BYTES_ATTEMPTS";
+const MAX_BLUR_RADIUS_KEY: &str = "MAX_BLUR_RADIUS";
+const MAX_CONVOLUTION_SIZE_KEY: &str = "MAX_CONVOLUTION_SIZE";
 
 #[derive(Debug, Clone)]
 pub struct Config {
@@ -25,6 +46,7 @@ pub struct Config {
     pub image_cache_settings: ImageCacheSettings,
     pub aws_settings: AwsSettings,
     pub validation_settings: ValidationSettings,
+    pub output_settings: OutputSettings,
 }
 
 #[derive(Clone, Debug)]
@@ -58,11 +80,33 @@ pub struct ValidationSettings {
     pub max_source_image_download_size: ByteSize,
     // Max image size
     pub max_source_image_size: ByteSize,
+    // Max width x height of an image we will encode as AVIF, which is slow
+    pub max_avif_output_pixels: u32,
+    // Max number of frames in an animated source image
+    pub max_frames: u32,
+    // Max number of nodes (shapes, groups and so on) in an SVG source
+    pub max_svg_nodes: u32,
+    // Max width x height an SVG source gets rendered at
+    pub max_svg_render_pixels: u32,
+    // Max number of times an image gets encoded to fit it in filters:max_bytes(n)
+    pub max_bytes_attempts: u32,
+    // Max radius of filters:blur() and filters:sharpen() (pixels)
+    pub max_blur_radius: u32,
+    // Max columns and rows of a filters:convolution() kernel
+    pub max_convolution_size: u32,
 }
 
 static MAX_PIXELS_DEFAULT: u32 = 10000;
 static MAX_IMAGE_DOWNLOAD_SIZE: ByteSize = ByteSize::mb(10);
 static MAX_IMAGE_FILE_SIZE: ByteSize = ByteSize::mb(10);
+static MAX_AVIF_OUTPUT_PIXELS: u32 = 4_000_000;
+static MAX_FRAMES: u32 = 200;
+static MAX_SVG_NODES: u32 = 10_000;
+static MAX_SVG_RENDER_PIXELS: u32 = 16_000_000;
+static MAX_BYTES_ATTEMPTS: u32 = 8;
+// Blurring costs as much per pixel as the kernel is wide, twice over
+static MAX_BLUR_RADIUS: u32 = 50;
+static MAX_CONVOLUTION_SIZE: u32 = 15;
 
 impl Default for ValidationSettings {
     fn default() -> Self {
@@ -73,6 +117,84 @@ impl Default for ValidationSettings {
             max_source_image_height: MAX_PIXELS_DEFAULT,
             max_source_image_download_size: MAX_IMAGE_DOWNLOAD_SIZE,
             max_source_image_size: MAX_IMAGE_FILE_SIZE,
+            max_avif_output_pixels: MAX_AVIF_OUTPUT_PIXELS,
+            max_frames: MAX_FRAMES,
+            max_svg_nodes: MAX_SVG_NODES,
+            max_svg_render_pixels: MAX_SVG_RENDER_PIXELS,
+            max_bytes_attempts: MAX_BYTES_ATTEMPTS,
+            max_blur_radius: MAX_BLUR_RADIUS,
+            max_convolution_size: MAX_CONVOLUTION_SIZE,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct OutputSettings {
+    // Serve WebP to clients that accept it, unless a format was asked for explicitly
+    pub auto_webp: bool,
+    // Serve AVIF to clients that accept it (preferred over WebP), unless a format was asked for explicitly
+    pub auto_avif: bool,
+    // Encoder quality (1-100) for JPEG, lossy WebP and AVIF when filters:quality() isn't used
+    pub default_quality: u8,
+    // Encoder quality (1-100) for AVIF when filters:quality() isn't used, None means default_quality
+    pub avif_quality: Option<u8>,
+    // AVIF encoder speed, from 1 (slowest, smallest files) to 10 (fastest)
+    pub avif_speed: u8,
+    // Encode WebP losslessly when filters:lossless() isn't used
+    pub webp_lossless: bool,
+    // Carry the source's EXIF (GPS included) over to the output, unless filters:strip_exif() is used
+    pub keep_exif: bool,
+    // Carry the source's ICC profile over to the output, unless filters:strip_icc() is used
+    pub keep_icc: bool,
+    pub icc_handling: IccHandling,
+    // Write JPEGs as progressive ones when filters:progressive() isn't used
+    pub progressive_jpeg: bool,
+    // Losslessly shrink JPEGs and PNGs (at some CPU cost) when filters:optimise() isn't used
+    pub optimise_output: bool,
+}
+
+/// What happens to sources with an embedded ICC profile (e.g. Adobe RGB or Display P3 photos)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum IccHandling {
+    // Turn the pixels into sRGB before processing, so the output looks right without the profile
+    #[default]
+    Convert,
+    // Leave the pixels alone, relying on the profile being embedded in the output (see keep_icc)
+    Embed,
+}
+
+impl FromStr for IccHandling {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "convert" => Ok(IccHandling::Convert),
+            "embed" => Ok(IccHandling::Embed),
+            _ => anyhow::bail!("Unknown ICC handling [{s}], must be convert or embed"),
+        }
+    }
+}
+
+const QUALITY_RANGE: RangeInclusive<u8> = 1..=100;
+const AVIF_SPEED_RANGE: RangeInclusive<u8> = 1..=10;
+static DEFAULT_QUALITY: u8 = 80;
+// Faster than cavif's default (4), as Lambda bills by the millisecond
+static DEFAULT_AVIF_SPEED: u8 = 6;
+
+impl Default for OutputSettings {
+    fn default() -> Self {
+        Self {
+            auto_webp: false,
+            auto_avif: false,
+            default_quality: DEFAULT_QUALITY,
+            avif_quality: None,
+            avif_speed: DEFAULT_AVIF_SPEED,
+            webp_lossless: false,
+            keep_exif: false,
+            keep_icc: true,
+            icc_handling: IccHandling::default(),
+            progressive_jpeg: false,
+            optimise_output: false,
         }
     }
 }
@@ -125,12 +247,70 @@ impl Config {
         if let Some(max_source_image_size) = read_env_var(MAX_IMAGE_FILE_SIZE_KEY)? {
             validation_settings.max_source_image_size = max_source_image_size;
         }
+        if let Some(max_avif_output_pixels) = read_env_var(MAX_AVIF_OUTPUT_PIXELS_KEY)? {
+            validation_settings.max_avif_output_pixels = max_avif_output_pixels;
+        }
+        if let Some(max_frames) = read_env_var(MAX_FRAMES_KEY)? {
+            validation_settings.max_frames = max_frames;
+        }
+        if let Some(max_svg_nodes) = read_env_var(MAX_SVG_NODES_KEY)? {
+            validation_settings.max_svg_nodes = max_svg_nodes;
+        }
+        if let Some(max_svg_render_pixels) = read_env_var(MAX_SVG_RENDER_PIXELS_KEY)? {
+            validation_settings.max_svg_render_pixels = max_svg_render_pixels;
+        }
+        if let Some(max_bytes_attempts) = read_env_var(MAX_BYTES_ATTEMPTS_KEY)? {
+            validation_settings.max_bytes_attempts = max_bytes_attempts;
+        }
+        if let Some(max_blur_radius) = read_env_var(MAX_BLUR_RADIUS_KEY)? {
+            validation_settings.max_blur_radius = max_blur_radius;
+        }
+        if let Some(max_convolution_size) = read_env_var(MAX_CONVOLUTION_SIZE_KEY)? {
+            validation_settings.max_convolution_size = max_convolution_size;
+        }
+
+        let mut output_settings = OutputSettings::default();
+
+        if let Some(auto_webp) = read_env_var(AUTO_WEBP_KEY)? {
+            output_settings.auto_webp = auto_webp;
+        }
+        if let Some(auto_avif) = read_env_var(AUTO_AVIF_KEY)? {
+            output_settings.auto_avif = auto_avif;
+        }
+        if let Some(default_quality) = read_env_var_in(DEFAULT_QUALITY_KEY, QUALITY_RANGE)? {
+            output_settings.default_quality = default_quality;
+        }
+        if let Some(avif_quality) = read_env_var_in(AVIF_QUALITY_KEY, QUALITY_RANGE)? {
+            output_settings.avif_quality = Some(avif_quality);
+        }
+        if let Some(avif_speed) = read_env_var_in(AVIF_SPEED_KEY, AVIF_SPEED_RANGE)? {
+            output_settings.avif_speed = avif_speed;
+        }
+        if let Some(webp_lossless) = read_env_var(WEBP_LOSSLESS_KEY)? {
+            output_settings.webp_lossless = webp_lossless;
+        }
+        if let Some(keep_exif) = read_env_var(KEEP_EXIF_KEY)? {
+            output_settings.keep_exif = keep_exif;
+        }
+        if let Some(keep_icc) = read_env_var(KEEP_ICC_KEY)? {
+            output_settings.keep_icc = keep_icc;
+        }
+        if let Some(icc_handling) = read_env_var(ICC_HANDLING_KEY)? {
+            output_settings.icc_handling = icc_handling;
+        }
+        if let Some(progressive_jpeg) = read_env_var(PROGRESSIVE_JPEG_KEY)? {
+            output_settings.progressive_jpeg = progressive_jpeg;
+        }
+        if let Some(optimise_output) = read_env_var(OPTIMISE_OUTPUT_KEY)? {
+            output_settings.optimise_output = optimise_output;
+        }
 
         Ok(Config {
             authentication_settings,
             image_cache_settings,
             aws_settings,
             validation_settings,
+            output_settings,
         })
     }
 }
@@ -150,3 +330,48 @@ where
         })?)),
     }
 }
+
+// Like read_env_var, but values outside of the range are an error too, rather than something
+// that only goes wrong once an image gets encoded
+fn read_env_var_in<T>(env_var_key: &str, range: RangeInclusive<T>) -> anyhow::Result<Option<T>>
+where
+    T: FromStr + PartialOrd + Display,
+    <T as FromStr>::Err: ToString,
+{
+    within(env_var_key, read_env_var(env_var_key)?, range)
+}
+
+fn within<T>(
+    env_var_key: &str,
+    value: Option<T>,
+    range: RangeInclusive<T>,
+) -> anyhow::Result<Option<T>>
+where
+    T: PartialOrd + Display,
+{
+    match value {
+        Some(value) if !range.contains(&value) => anyhow::bail!(
+            "{env_var_key} [{value}] is out of range, must be from {} to {}",
+            range.start(),
+            range.end()
+        ),
+        value => Ok(value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_within() {
+        let key = "DEFAULT_QUALITY";
+        assert_eq!(None, within(key, None, QUALITY_RANGE).unwrap());
+        assert_eq!(Some(100), within(key, Some(100), QUALITY_RANGE).unwrap());
+        assert_eq!(
+            "DEFAULT_QUALITY [0] is out of range, must be from 1 to 100",
+            within(key, Some(0), QUALITY_RANGE).unwrap_err().to_string()
+        );
+        assert!(within(key, Some(11), AVIF_SPEED_RANGE).is_err());
+    }
+}
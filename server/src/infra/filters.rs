@@ -0,0 +1,1028 @@
+use std::fmt;
+use std::str::FromStr;
+
+use image::{ColorType, DynamicImage, Rgba32FImage};
+use serde::{Deserialize, Serialize};
+
+use super::config::ValidationSettings;
+use super::image_encoding::OutputOptions;
+use super::image_manipulation::{Fill, ResizeMode, ResizeOptions};
+
+/// A filter that works on the image itself, run after trimming, cropping and resizing
+#[derive(PartialEq, Eq, Debug, Serialize, Deserialize, Clone)]
+pub enum Filter {
+    Grayscale,
+    // Amounts are percentages, from -100 to 100
+    Brightness(i32),
+    Contrast(i32),
+    Saturation(i32),
+    Rgb(i32, i32, i32),
+    // Spreads each channel's values over the whole range
+    Equalize,
+    // Gaussian blur over radius pixels either side, sigma defaults to the radius
+    Blur {
+        radius: u32,
+        sigma: Option<Decimal>,
+    },
+    // Unsharp mask: adds amount times the detail a Gaussian blur (with radius as sigma) removes
+    Sharpen {
+        amount: Decimal,
+        radius: Decimal,
+        luminance_only: bool,
+    },
+    // Random noise of up to this many levels (out of 255), from 0 to 100
+    Noise(i32),
+    // Weights are given row by row, with the kernel centred on each pixel
+    Convolution {
+        matrix: Vec<Decimal>,
+        columns: u32,
+        normalize: bool,
+    },
+}
+
+/// A number with up to two decimal places, kept as hundredths so that filters compare exactly
+#[derive(PartialEq, Eq, Debug, Serialize, Deserialize, Clone, Copy)]
+pub struct Decimal(i32);
+
+impl Decimal {
+    pub fn from_hundredths(hundredths: i32) -> Self {
+        Decimal(hundredths)
+    }
+
+    pub fn hundredths(self) -> i32 {
+        self.0
+    }
+
+    pub fn as_f32(self) -> f32 {
+        self.0 as f32 / 100.0
+    }
+}
+
+impl FromStr for Decimal {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse::<f64>()
+            .ok()
+            .map(|number| (number * 100.0).round())
+            .filter(|hundredths| (i32::MIN as f64..=i32::MAX as f64).contains(hundredths))
+            .map(|hundredths| Decimal(hundredths as i32))
+            .ok_or_else(|| anyhow::anyhow!("Invalid number [{s}]"))
+    }
+}
+
+impl fmt::Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0 as f64 / 100.0)
+    }
+}
+
+impl Filter {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Filter::Grayscale => "grayscale",
+            Filter::Brightness(_) => "brightness",
+            Filter::Contrast(_) => "contrast",
+            Filter::Saturation(_) => "saturation",
+            Filter::Rgb(..) => "rgb",
+            Filter::Equalize => "equalize",
+            Filter::Blur { .. } => "blur",
+            Filter::Sharpen { .. } => "sharpen",
+            Filter::Noise(_) => "noise",
+            Filter::Convolution { .. } => "convolution",
+        }
+    }
+
+    /// The arguments as they'd be written in the path
+    pub fn args(&self) -> Vec<String> {
+        match self {
+            Filter::Grayscale | Filter::Equalize => Vec::new(),
+            Filter::Brightness(amount)
+            | Filter::Contrast(amount)
+            | Filter::Saturation(amount)
+            | Filter::Noise(amount) => vec![amount.to_string()],
+            Filter::Rgb(r, g, b) => vec![r.to_string(), g.to_string(), b.to_string()],
+            Filter::Blur { radius, sigma } => std::iter::once(radius.to_string())
+                .chain(sigma.map(|sigma| sigma.to_string()))
+                .collect(),
+            Filter::Sharpen {
+                amount,
+                radius,
+                luminance_only,
+            } => vec![
+                amount.to_string(),
+                radius.to_string(),
+                luminance_only.to_string(),
+            ],
+            Filter::Convolution {
+                matrix,
+                columns,
+                normalize,
+            } => {
+                let weights: Vec<String> = matrix.iter().map(Decimal::to_string).collect();
+                vec![
+                    weights.join(";"),
+                    columns.to_string(),
+                    normalize.to_string(),
+                ]
+            }
+        }
+    }
+
+    pub fn validate(&self, settings: &ValidationSettings) -> Vec<String> {
+        let name = self.name();
+        let max_radius = settings.max_blur_radius;
+        match self {
+            Filter::Grayscale | Filter::Equalize => Vec::new(),
+            Filter::Brightness(amount) | Filter::Contrast(amount) | Filter::Saturation(amount) => {
+                self.validate_amounts(&[*amount])
+            }
+            Filter::Rgb(r, g, b) => self.validate_amounts(&[*r, *g, *b]),
+            Filter::Blur { radius, sigma } => {
+                let mut problems = Vec::new();
+                if *radius > max_radius {
+                    problems.push(format!(
+                        "Filter [{name}] radius [{radius}] too large, must be [{max_radius}] or lower"
+                    ));
+                }
+                if let Some(sigma) = sigma.filter(|sigma| sigma.0 <= 0) {
+                    problems.push(format!(
+                        "Filter [{name}] sigma [{sigma}] must be more than 0"
+                    ));
+                }
+                problems
+            }
+            Filter::Sharpen { radius, .. } => {
+                if radius.0 <= 0 {
+                    vec![format!(
+                        "Filter [{name}] radius [{radius}] must be more than 0"
+                    )]
+                } else if sharpen_kernel_radius(*radius) > max_radius {
+                    vec![format!(
+                        "Filter [{name}] radius [{radius}] too large, its blur reaches [{}] pixels out, must be [{max_radius}] or lower",
+                        sharpen_kernel_radius(*radius)
+                    )]
+                } else {
+                    Vec::new()
+                }
+            }
+            Filter::Noise(amount) => {
+                if (0..=100).contains(amount) {
+                    Vec::new()
+                } else {
+                    vec![format!(
+                        "Filter [{name}] amount [{amount}] must be between 0 and 100"
+                    )]
+                }
+            }
+            Filter::Convolution {
+                matrix, columns, ..
+            } => {
+                let weights = matrix.len() as u32;
+                let max_size = settings.max_convolution_size;
+                if weights == 0 || *columns == 0 || !weights.is_multiple_of(*columns) {
+                    vec![format!(
+                        "Filter [{name}] matrix of [{weights}] weights can't be split into rows of [{columns}]"
+                    )]
+                } else if *columns > max_size || weights / columns > max_size {
+                    vec![format!(
+                        "Filter [{name}] kernel [{columns}x{}] too large, must be [{max_size}x{max_size}] or smaller",
+                        weights / columns
+                    )]
+                } else {
+                    Vec::new()
+                }
+            }
+        }
+    }
+
+    fn validate_amounts(&self, amounts: &[i32]) -> Vec<String> {
+        amounts
+            .iter()
+            .filter(|amount| !(-100..=100).contains(*amount))
+            .map(|amount| {
+                format!(
+                    "Filter [{}] amount [{amount}] must be between -100 and 100",
+                    self.name()
+                )
+            })
+            .collect()
+    }
+
+    pub fn apply(&self, image: DynamicImage) -> DynamicImage {
+        match self {
+            Filter::Grayscale => image.grayscale(),
+            Filter::Brightness(amount) => {
+                let change = *amount as f32 / 100.0;
+                map_colours(image, |colour| colour.map(|c| c + change))
+            }
+            Filter::Contrast(amount) => {
+                // Stretching (or squashing) the values around the middle, down to plain grey at -100
+                let factor = ((100.0 + *amount as f32) / 100.0).powi(2);
+                map_colours(image, |colour| colour.map(|c| (c - 0.5) * factor + 0.5))
+            }
+            Filter::Saturation(amount) => {
+                let factor = 1.0 + *amount as f32 / 100.0;
+                map_colours(image, |colour| {
+                    // Moving away from (or towards) the grey of the same luma
+                    let luma = luma(colour);
+                    colour.map(|c| luma + (c - luma) * factor)
+                })
+            }
+            Filter::Rgb(r, g, b) => {
+                let changes = [r, g, b].map(|amount| *amount as f32 / 100.0);
+                map_colours(image, |colour| {
+                    std::array::from_fn(|channel| colour[channel] + changes[channel])
+                })
+            }
+            Filter::Equalize => {
+                let mappings = equalize_mappings(&image);
+                map_colours(image, |colour| {
+                    std::array::from_fn(|channel| {
+                        let bin = (colour[channel] * 255.0).round() as usize;
+                        mappings[channel][bin.min(255)]
+                    })
+                })
+            }
+            Filter::Blur { radius: 0, .. } => image,
+            Filter::Blur { radius, sigma } => {
+                let sigma = sigma.map_or(*radius as f32, Decimal::as_f32);
+                let blurred = gaussian_blur(&image.to_rgba32f(), *radius, sigma);
+                from_rgba32f(blurred, image.color())
+            }
+            Filter::Sharpen {
+                amount,
+                radius,
+                luminance_only,
+            } => {
+                let blurred = gaussian_blur(
+                    &image.to_rgba32f(),
+                    sharpen_kernel_radius(*radius),
+                    radius.as_f32(),
+                );
+                let amount = amount.as_f32();
+                map_pixels(image, |x, y, colour| {
+                    let [r, g, b, _] = blurred.get_pixel(x, y).0;
+                    let detail: [f32; 3] = if *luminance_only {
+                        [luma(colour) - luma([r, g, b]); 3]
+                    } else {
+                        [colour[0] - r, colour[1] - g, colour[2] - b]
+                    };
+                    std::array::from_fn(|channel| colour[channel] + amount * detail[channel])
+                })
+            }
+            Filter::Noise(amount) => {
+                let levels = *amount as f32 / 255.0;
+                map_pixels(image, |x, y, colour| {
+                    std::array::from_fn(|channel| colour[channel] + levels * noise(x, y, channel))
+                })
+            }
+            Filter::Convolution {
+                matrix,
+                columns,
+                normalize,
+            } => {
+                let mut weights: Vec<f32> = matrix.iter().map(|weight| weight.as_f32()).collect();
+                let total: f32 = weights.iter().sum();
+                if *normalize && total != 0.0 {
+                    weights.iter_mut().for_each(|weight| *weight /= total);
+                }
+                let convolved = convolve(&image.to_rgba32f(), &weights, *columns, false);
+                from_rgba32f(convolved, image.color())
+            }
+        }
+    }
+}
+
+// Sharpen's radius is the sigma of its blur, which covers three times that to take in nearly all
+// of the curve
+fn sharpen_kernel_radius(radius: Decimal) -> u32 {
+    (radius.as_f32() * 3.0).ceil() as u32
+}
+
+fn luma([r, g, b]: [f32; 3]) -> f32 {
+    0.2126 * r + 0.7152 * g + 0.0722 * b
+}
+
+// Runs the function over the colour of every pixel (as 0 to 1 values, clamped afterwards),
+// keeping alpha and the extra depth of 16-bit images
+fn map_colours(image: DynamicImage, f: impl Fn([f32; 3]) -> [f32; 3]) -> DynamicImage {
+    map_pixels(image, |_, _, colour| f(colour))
+}
+
+// Like map_colours, for when the position of the pixel matters too
+fn map_pixels(image: DynamicImage, f: impl Fn(u32, u32, [f32; 3]) -> [f32; 3]) -> DynamicImage {
+    let colour_type = image.color();
+    let mut pixels = image.to_rgba32f();
+    for (x, y, pixel) in pixels.enumerate_pixels_mut() {
+        let [r, g, b, a] = pixel.0;
+        let [r, g, b] = f(x, y, [r, g, b]).map(|c| c.clamp(0.0, 1.0));
+        pixel.0 = [r, g, b, a];
+    }
+    from_rgba32f(pixels, colour_type)
+}
+
+/// Back to 8 or 16 bits, with or without alpha, depending on the colour type
+pub fn from_rgba32f(pixels: Rgba32FImage, colour_type: ColorType) -> DynamicImage {
+    let has_alpha = colour_type.has_alpha();
+    let deep = colour_type.bytes_per_pixel() / colour_type.channel_count() > 1;
+    let pixels = DynamicImage::ImageRgba32F(pixels);
+    match (deep, has_alpha) {
+        (true, true) => DynamicImage::ImageRgba16(pixels.to_rgba16()),
+        (true, false) => DynamicImage::ImageRgb16(pixels.to_rgb16()),
+        (false, true) => DynamicImage::ImageRgba8(pixels.to_rgba8()),
+        (false, false) => DynamicImage::ImageRgb8(pixels.to_rgb8()),
+    }
+}
+
+/// Separable, so done as a horizontal then a vertical pass
+pub fn gaussian_blur(pixels: &Rgba32FImage, radius: u32, sigma: f32) -> Rgba32FImage {
+    let kernel: Vec<f32> = (-(radius as i64)..=radius as i64)
+        .map(|offset| (-((offset * offset) as f32) / (2.0 * sigma * sigma)).exp())
+        .collect();
+    let total: f32 = kernel.iter().sum();
+    let kernel: Vec<f32> = kernel.iter().map(|weight| weight / total).collect();
+    let size = kernel.len() as u32;
+    let horizontal = convolve(pixels, &kernel, size, true);
+    convolve(&horizontal, &kernel, 1, true)
+}
+
+// Sums the weighted neighbours of every pixel, repeating the edge pixels past the borders.
+// Alpha is either blurred along with the colour (weighting colours by it so that transparent
+// pixels don't bleed in), or left as it is.
+fn convolve(pixels: &Rgba32FImage, kernel: &[f32], columns: u32, with_alpha: bool) -> Rgba32FImage {
+    let rows = kernel.len() as u32 / columns;
+    let (width, height) = pixels.dimensions();
+    Rgba32FImage::from_fn(width, height, |x, y| {
+        let mut sum = [0.0; 4];
+        for (i, weight) in kernel.iter().enumerate() {
+            let dx = (i as u32 % columns) as i64 - (columns / 2) as i64;
+            let dy = (i as u32 / columns) as i64 - (rows / 2) as i64;
+            let sx = (x as i64 + dx).clamp(0, width as i64 - 1) as u32;
+            let sy = (y as i64 + dy).clamp(0, height as i64 - 1) as u32;
+            let [r, g, b, a] = pixels.get_pixel(sx, sy).0;
+            let sample = if with_alpha {
+                [r * a, g * a, b * a, a]
+            } else {
+                [r, g, b, a]
+            };
+            for (total, value) in sum.iter_mut().zip(sample) {
+                *total += weight * value;
+            }
+        }
+        let [r, g, b, a] = sum;
+        let pixel = if !with_alpha {
+            [r, g, b, pixels.get_pixel(x, y)[3]]
+        } else if a > 0.0 {
+            [r / a, g / a, b / a, a]
+        } else {
+            [0.0; 4]
+        };
+        image::Rgba(pixel.map(|c| c.clamp(0.0, 1.0)))
+    })
+}
+
+// From -1 to 1, the same for the same pixel and channel every time so that images are
+// reproducible (SplitMix64 on the position)
+fn noise(x: u32, y: u32, channel: usize) -> f32 {
+    let mut z =
+        ((y as u64) << 34 | (x as u64) << 2 | channel as u64).wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^= z >> 31;
+    (z >> 40) as f32 / (1u64 << 23) as f32 - 1.0
+}
+
+// For each channel, what each of its 256 levels ends up as (from 0 to 1) once the cumulative
+// histogram is stretched over the whole range
+fn equalize_mappings(image: &DynamicImage) -> [[f32; 256]; 3] {
+    let mut histograms = [[0u64; 256]; 3];
+    for pixel in image.to_rgb8().pixels() {
+        for (histogram, value) in histograms.iter_mut().zip(pixel.0) {
+            histogram[value as usize] += 1;
+        }
+    }
+    histograms.map(|histogram| {
+        let total: u64 = histogram.iter().sum();
+        let darkest = histogram
+            .iter()
+            .copied()
+            .find(|count| *count > 0)
+            .unwrap_or(0);
+        let mut cumulative = 0;
+        std::array::from_fn(|level| {
+            cumulative += histogram[level];
+            if total == darkest {
+                // A single level has nowhere to be spread to
+                level as f32 / 255.0
+            } else {
+                cumulative.saturating_sub(darkest) as f32 / (total - darkest) as f32
+            }
+        })
+    })
+}
+
+/// Everything the filters in a path add up to
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct ParsedFilters {
+    pub resize_options: ResizeOptions,
+    pub filters: Vec<Filter>,
+    pub output: OutputOptions,
+    pub no_autorotate: bool,
+    // Counter-clockwise degrees, each along with how many of the filters come before it, so
+    // that they run in the order they are given
+    pub rotations: Vec<(usize, Decimal)>,
+    // What the canvas left uncovered by padding fit-in results or rotating gets filled with
+    pub fill: Option<Fill>,
+}
+
+type FilterParser = fn(&[String], &mut ParsedFilters) -> anyhow::Result<()>;
+
+// Adding a filter means adding an entry here (and a Filter variant if it works on the image)
+const REGISTRY: &[(&str, FilterParser)] = &[
+    ("stretch", |args, parsed| {
+        expect_args("stretch", args, 0)?;
+        parsed.resize_options.mode = ResizeMode::Stretch;
+        Ok(())
+    }),
+    ("focal", |args, parsed| {
+        expect_args("focal", args, 1)?;
+        parsed.resize_options.focal = Some(args[0].parse()?);
+        Ok(())
+    }),
+    ("format", |args, parsed| {
+        expect_args("format", args, 1)?;
+        parsed.output.format = Some(args[0].parse()?);
+        Ok(())
+    }),
+    ("quality", |args, parsed| {
+        expect_args("quality", args, 1)?;
+        let quality = args[0]
+            .parse()
+            .map_err(|_| anyhow::anyhow!("Invalid quality [{}]", args[0]))?;
+        parsed.output.quality = Some(quality);
+        Ok(())
+    }),
+    ("lossless", |args, parsed| {
+        expect_args("lossless", args, 0)?;
+        parsed.output.lossless = Some(true);
+        Ok(())
+    }),
+    ("progressive", |args, parsed| {
+        expect_args("progressive", args, 0)?;
+        parsed.output.progressive = Some(true);
+        Ok(())
+    }),
+    ("optimise", |args, parsed| {
+        expect_args("optimise", args, 0)?;
+        parsed.output.optimise = Some(true);
+        Ok(())
+    }),
+    ("max_bytes", |args, parsed| {
+        expect_args("max_bytes", args, 1)?;
+        let max_bytes = args[0]
+            .parse()
+            .map_err(|_| anyhow::anyhow!("Invalid max bytes [{}]", args[0]))?;
+        parsed.output.max_bytes = Some(max_bytes);
+        Ok(())
+    }),
+    ("strip_exif", |args, parsed| {
+        expect_args("strip_exif", args, 0)?;
+        parsed.output.strip_exif = true;
+        Ok(())
+    }),
+    ("strip_icc", |args, parsed| {
+        expect_args("strip_icc", args, 0)?;
+        parsed.output.strip_icc = true;
+        Ok(())
+    }),
+    ("no_autorotate", |args, parsed| {
+        expect_args("no_autorotate", args, 0)?;
+        parsed.no_autorotate = true;
+        Ok(())
+    }),
+    ("rotate", |args, parsed| {
+        expect_args("rotate", args, 1)?;
+        let degrees = args[0].parse()?;
+        parsed.rotations.push((parsed.filters.len(), degrees));
+        Ok(())
+    }),
+    ("fill", |args, parsed| {
+        expect_args("fill", args, 1)?;
+        parsed.fill = Some(args[0].parse()?);
+        Ok(())
+    }),
+    ("grayscale", |args, parsed| {
+        expect_args("grayscale", args, 0)?;
+        parsed.filters.push(Filter::Grayscale);
+        Ok(())
+    }),
+    ("brightness", |args, parsed| {
+        expect_args("brightness", args, 1)?;
+        parsed
+            .filters
+            .push(Filter::Brightness(parse_amount(&args[0])?));
+        Ok(())
+    }),
+    ("contrast", |args, parsed| {
+        expect_args("contrast", args, 1)?;
+        parsed
+            .filters
+            .push(Filter::Contrast(parse_amount(&args[0])?));
+        Ok(())
+    }),
+    ("saturation", |args, parsed| {
+        expect_args("saturation", args, 1)?;
+        parsed
+            .filters
+            .push(Filter::Saturation(parse_amount(&args[0])?));
+        Ok(())
+    }),
+    ("rgb", |args, parsed| {
+        expect_args("rgb", args, 3)?;
+        parsed.filters.push(Filter::Rgb(
+            parse_amount(&args[0])?,
+            parse_amount(&args[1])?,
+            parse_amount(&args[2])?,
+        ));
+        Ok(())
+    }),
+    ("equalize", |args, parsed| {
+        expect_args("equalize", args, 0)?;
+        parsed.filters.push(Filter::Equalize);
+        Ok(())
+    }),
+    ("blur", |args, parsed| {
+        expect_args_between("blur", args, 1, 2)?;
+        let radius = args[0]
+            .parse()
+            .map_err(|_| anyhow::anyhow!("Invalid radius [{}]", args[0]))?;
+        let sigma = args.get(1).map(|sigma| sigma.parse()).transpose()?;
+        parsed.filters.push(Filter::Blur { radius, sigma });
+        Ok(())
+    }),
+    ("sharpen", |args, parsed| {
+        expect_args("sharpen", args, 3)?;
+        parsed.filters.push(Filter::Sharpen {
+            amount: args[0].parse()?,
+            radius: args[1].parse()?,
+            luminance_only: parse_bool(&args[2])?,
+        });
+        Ok(())
+    }),
+    ("noise", |args, parsed| {
+        expect_args("noise", args, 1)?;
+        parsed.filters.push(Filter::Noise(parse_amount(&args[0])?));
+        Ok(())
+    }),
+    ("convolution", |args, parsed| {
+        expect_args("convolution", args, 3)?;
+        let matrix = args[0]
+            .split(';')
+            .map(str::parse)
+            .collect::<anyhow::Result<_>>()?;
+        let columns = args[1]
+            .parse()
+            .map_err(|_| anyhow::anyhow!("Invalid columns [{}]", args[1]))?;
+        parsed.filters.push(Filter::Convolution {
+            matrix,
+            columns,
+            normalize: parse_bool(&args[2])?,
+        });
+        Ok(())
+    }),
+];
+
+// Ranges get checked when validating, so that every problem is reported at once
+fn parse_amount(arg: &str) -> anyhow::Result<i32> {
+    arg.parse()
+        .map_err(|_| anyhow::anyhow!("Invalid amount [{arg}]"))
+}
+
+fn parse_bool(arg: &str) -> anyhow::Result<bool> {
+    arg.parse()
+        .map_err(|_| anyhow::anyhow!("Invalid boolean [{arg}], must be true or false"))
+}
+
+/// Applies the named filter to what has been parsed so far, or returns None for unknown filters
+pub fn parse_filter(
+    name: &str,
+    args: &[String],
+    parsed: &mut ParsedFilters,
+) -> Option<anyhow::Result<()>> {
+    REGISTRY
+        .iter()
+        .find(|(registered, _)| *registered == name)
+        .map(|(_, parser)| parser(args, parsed))
+}
+
+fn expect_args(name: &str, args: &[String], expected: usize) -> anyhow::Result<()> {
+    if args.len() == expected {
+        Ok(())
+    } else {
+        anyhow::bail!(
+            "Filter [{name}] takes [{expected}] argument(s), but got [{}]",
+            args.len()
+        )
+    }
+}
+
+fn expect_args_between(name: &str, args: &[String], min: usize, max: usize) -> anyhow::Result<()> {
+    if (min..=max).contains(&args.len()) {
+        Ok(())
+    } else {
+        anyhow::bail!(
+            "Filter [{name}] takes [{min}] to [{max}] arguments, but got [{}]",
+            args.len()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::infra::image_encoding::OutputFormat;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_filter() -> anyhow::Result<()> {
+        let mut parsed = ParsedFilters::default();
+        parse_filter("stretch", &[], &mut parsed).unwrap()?;
+        parse_filter("grayscale", &[], &mut parsed).unwrap()?;
+        assert_eq!(ResizeMode::Stretch, parsed.resize_options.mode);
+        assert_eq!(vec![Filter::Grayscale], parsed.filters);
+
+        parse_filter("format", &["webp".to_string()], &mut parsed).unwrap()?;
+        assert_eq!(Some(OutputFormat::WebP), parsed.output.format);
+        assert!(parse_filter("format", &["bmp".to_string()], &mut parsed)
+            .unwrap()
+            .is_err());
+
+        parse_filter("quality", &["55".to_string()], &mut parsed).unwrap()?;
+        assert_eq!(Some(55), parsed.output.quality);
+        parse_filter("lossless", &[], &mut parsed).unwrap()?;
+        assert_eq!(Some(true), parsed.output.lossless);
+        assert!(parse_filter("quality", &["lol".to_string()], &mut parsed)
+            .unwrap()
+            .is_err());
+
+        parse_filter("progressive", &[], &mut parsed).unwrap()?;
+        parse_filter("optimise", &[], &mut parsed).unwrap()?;
+        assert_eq!(
+            (Some(true), Some(true)),
+            (parsed.output.progressive, parsed.output.optimise)
+        );
+
+        parse_filter("max_bytes", &["50000".to_string()], &mut parsed).unwrap()?;
+        assert_eq!(Some(50_000), parsed.output.max_bytes);
+        assert!(
+            parse_filter("max_bytes", &["50kb".to_string()], &mut parsed)
+                .unwrap()
+                .is_err()
+        );
+
+        parse_filter("strip_exif", &[], &mut parsed).unwrap()?;
+        parse_filter("strip_icc", &[], &mut parsed).unwrap()?;
+        assert!(parsed.output.strip_exif && parsed.output.strip_icc);
+
+        parse_filter("no_autorotate", &[], &mut parsed).unwrap()?;
+        assert!(parsed.no_autorotate);
+
+        assert!(parse_filter("lol", &[], &mut parsed).is_none());
+        let error = parse_filter("grayscale", &["1".to_string()], &mut parsed)
+            .unwrap()
+            .err()
+            .unwrap();
+        assert_eq!(
+            "Filter [grayscale] takes [0] argument(s), but got [1]",
+            error.to_string()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_grayscale() {
+        let image =
+            DynamicImage::ImageRgb8(image::RgbImage::from_pixel(2, 2, image::Rgb([255, 0, 0])));
+        let result = Filter::Grayscale.apply(image).to_rgb8();
+        let pixel = result.get_pixel(0, 0);
+        assert_eq!(pixel[0], pixel[1]);
+        assert_eq!(pixel[1], pixel[2]);
+    }
+
+    #[test]
+    fn test_parse_tone_filters() -> anyhow::Result<()> {
+        let mut parsed = ParsedFilters::default();
+        parse_filter("brightness", &["10".to_string()], &mut parsed).unwrap()?;
+        parse_filter("contrast", &["-20".to_string()], &mut parsed).unwrap()?;
+        parse_filter("saturation", &["30".to_string()], &mut parsed).unwrap()?;
+        let rgb = ["1", "-2", "3"].map(str::to_string);
+        parse_filter("rgb", &rgb, &mut parsed).unwrap()?;
+        parse_filter("equalize", &[], &mut parsed).unwrap()?;
+        assert_eq!(
+            vec![
+                Filter::Brightness(10),
+                Filter::Contrast(-20),
+                Filter::Saturation(30),
+                Filter::Rgb(1, -2, 3),
+                Filter::Equalize
+            ],
+            parsed.filters
+        );
+        assert_eq!(vec!["1", "-2", "3"], Filter::Rgb(1, -2, 3).args());
+
+        let error = parse_filter("brightness", &["lots".to_string()], &mut parsed)
+            .unwrap()
+            .err()
+            .unwrap();
+        assert_eq!("Invalid amount [lots]", error.to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_tone_filters() {
+        let settings = ValidationSettings::default();
+        assert!(Filter::Brightness(-100).validate(&settings).is_empty());
+        assert_eq!(
+            vec!["Filter [contrast] amount [101] must be between -100 and 100"],
+            Filter::Contrast(101).validate(&settings)
+        );
+        assert_eq!(2, Filter::Rgb(-101, 0, 200).validate(&settings).len());
+    }
+
+    #[test]
+    fn test_tone_filters() {
+        let pixel = |filter: Filter, rgb: [u8; 3]| {
+            let image = DynamicImage::ImageRgb8(image::RgbImage::from_pixel(2, 2, image::Rgb(rgb)));
+            filter.apply(image).to_rgb8().get_pixel(0, 0).0
+        };
+        assert_eq!([151, 76, 26], pixel(Filter::Brightness(10), [125, 50, 0]));
+        assert_eq!([0, 0, 0], pixel(Filter::Brightness(-100), [200, 100, 0]));
+        assert_eq!(
+            [100, 100, 100],
+            pixel(Filter::Saturation(0), [100, 100, 100])
+        );
+        let [r, g, b] = pixel(Filter::Saturation(-100), [200, 50, 50]);
+        assert!(r == g && g == b);
+        let [r, g, _] = pixel(Filter::Saturation(50), [200, 50, 50]);
+        assert!(r > 200 && g < 50);
+        assert_eq!([128, 25, 0], pixel(Filter::Rgb(50, -10, 0), [0, 50, 0]));
+        let [r, ..] = pixel(Filter::Contrast(50), [200, 200, 200]);
+        assert!(r > 200);
+        assert_eq!([178, 66, 255], pixel(Filter::Contrast(50), [150, 100, 250]));
+        assert_eq!(
+            [128, 128, 128],
+            pixel(Filter::Contrast(-100), [10, 100, 250])
+        );
+    }
+
+    #[test]
+    fn test_tone_filters_keep_alpha_and_depth() {
+        let image = DynamicImage::ImageRgba16(image::ImageBuffer::from_pixel(
+            2,
+            2,
+            image::Rgba([30_000, 20_000, 10_000, 1_234]),
+        ));
+        for filter in [Filter::Brightness(0), Filter::Contrast(0)] {
+            let DynamicImage::ImageRgba16(result) = filter.apply(image.clone()) else {
+                panic!("Expected a 16-bit RGBA image");
+            };
+            assert_eq!(
+                &image::Rgba([30_000, 20_000, 10_000, 1_234]),
+                result.get_pixel(0, 0)
+            );
+        }
+    }
+
+    #[test]
+    fn test_equalize() {
+        // Values bunched up in the middle get spread out over the whole range
+        let image = DynamicImage::ImageLuma8(image::GrayImage::from_fn(4, 1, |x, _| {
+            image::Luma([100 + x as u8 * 10])
+        }));
+        let result = Filter::Equalize.apply(image).to_luma8();
+        let values: Vec<u8> = result.pixels().map(|p| p[0]).collect();
+        assert_eq!(vec![0, 85, 170, 255], values);
+
+        // Nothing to spread a flat image over
+        let flat =
+            DynamicImage::ImageRgb8(image::RgbImage::from_pixel(2, 2, image::Rgb([9, 9, 9])));
+        assert_eq!(
+            &image::Rgb([9, 9, 9]),
+            Filter::Equalize.apply(flat).to_rgb8().get_pixel(0, 0)
+        );
+    }
+
+    #[test]
+    fn test_parse_detail_filters() -> anyhow::Result<()> {
+        let mut parsed = ParsedFilters::default();
+        let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+        parse_filter("blur", &args(&["5"]), &mut parsed).unwrap()?;
+        parse_filter("blur", &args(&["5", "2.5"]), &mut parsed).unwrap()?;
+        parse_filter("sharpen", &args(&["1.5", "0.5", "true"]), &mut parsed).unwrap()?;
+        parse_filter("noise", &args(&["20"]), &mut parsed).unwrap()?;
+        parse_filter("convolution", &args(&["-1;0;1", "3", "false"]), &mut parsed).unwrap()?;
+        assert_eq!(
+            vec![
+                Filter::Blur {
+                    radius: 5,
+                    sigma: None
+                },
+                Filter::Blur {
+                    radius: 5,
+                    sigma: Some(Decimal::from_hundredths(250))
+                },
+                Filter::Sharpen {
+                    amount: Decimal::from_hundredths(150),
+                    radius: Decimal::from_hundredths(50),
+                    luminance_only: true
+                },
+                Filter::Noise(20),
+                Filter::Convolution {
+                    matrix: [-100, 0, 100].map(Decimal::from_hundredths).to_vec(),
+                    columns: 3,
+                    normalize: false
+                }
+            ],
+            parsed.filters
+        );
+        assert_eq!(vec!["5", "2.5"], parsed.filters[1].args());
+        assert_eq!(vec!["1.5", "0.5", "true"], parsed.filters[2].args());
+        assert_eq!(vec!["-1;0;1", "3", "false"], parsed.filters[4].args());
+
+        let error = |name: &str, given: &[&str]| {
+            let mut parsed = ParsedFilters::default();
+            parse_filter(name, &args(given), &mut parsed)
+                .unwrap()
+                .err()
+                .unwrap()
+                .to_string()
+        };
+        assert_eq!(
+            "Filter [blur] takes [1] to [2] arguments, but got [0]",
+            error("blur", &[])
+        );
+        assert_eq!("Invalid radius [-1]", error("blur", &["-1"]));
+        assert_eq!(
+            "Invalid number [lots]",
+            error("sharpen", &["lots", "1", "true"])
+        );
+        assert_eq!(
+            "Invalid boolean [yes], must be true or false",
+            error("convolution", &["1", "1", "yes"])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_detail_filters() {
+        let settings = ValidationSettings {
+            max_blur_radius: 10,
+            max_convolution_size: 3,
+            ..ValidationSettings::default()
+        };
+        let blur = |radius, sigma: Option<i32>| Filter::Blur {
+            radius,
+            sigma: sigma.map(Decimal::from_hundredths),
+        };
+        assert!(blur(10, Some(1)).validate(&settings).is_empty());
+        assert_eq!(
+            vec![
+                "Filter [blur] radius [11] too large, must be [10] or lower",
+                "Filter [blur] sigma [0] must be more than 0"
+            ],
+            blur(11, Some(0)).validate(&settings)
+        );
+        let sharpen = |radius| Filter::Sharpen {
+            amount: Decimal::from_hundredths(100),
+            radius: Decimal::from_hundredths(radius),
+            luminance_only: false,
+        };
+        assert!(sharpen(333).validate(&settings).is_empty());
+        assert_eq!(
+            vec!["Filter [sharpen] radius [3.34] too large, its blur reaches [11] pixels out, must be [10] or lower"],
+            sharpen(334).validate(&settings)
+        );
+        assert_eq!(
+            vec!["Filter [sharpen] radius [0] must be more than 0"],
+            sharpen(0).validate(&settings)
+        );
+        assert_eq!(
+            vec!["Filter [noise] amount [-1] must be between 0 and 100"],
+            Filter::Noise(-1).validate(&settings)
+        );
+        let convolution = |weights, columns| Filter::Convolution {
+            matrix: vec![Decimal::from_hundredths(100); weights],
+            columns,
+            normalize: true,
+        };
+        assert!(convolution(9, 3).validate(&settings).is_empty());
+        assert_eq!(
+            vec!["Filter [convolution] matrix of [9] weights can't be split into rows of [2]"],
+            convolution(9, 2).validate(&settings)
+        );
+        assert_eq!(
+            vec!["Filter [convolution] kernel [1x4] too large, must be [3x3] or smaller"],
+            convolution(4, 1).validate(&settings)
+        );
+    }
+
+    #[test]
+    fn test_blur() {
+        // A single bright pixel gets spread around, and a flat image stays flat
+        let dot = DynamicImage::ImageLuma8(image::GrayImage::from_fn(5, 5, |x, y| {
+            image::Luma([if (x, y) == (2, 2) { 255 } else { 0 }])
+        }));
+        let blurred = Filter::Blur {
+            radius: 1,
+            sigma: None,
+        }
+        .apply(dot)
+        .to_luma8();
+        assert!(blurred.get_pixel(2, 2)[0] < 255);
+        assert!(blurred.get_pixel(1, 2)[0] > 0);
+        assert_eq!(0, blurred.get_pixel(0, 0)[0]);
+
+        // Transparent pixels don't darken their neighbours
+        let half_transparent = DynamicImage::ImageRgba8(image::RgbaImage::from_fn(4, 1, |x, _| {
+            if x < 2 {
+                image::Rgba([255, 255, 255, 255])
+            } else {
+                image::Rgba([0, 0, 0, 0])
+            }
+        }));
+        let blurred = Filter::Blur {
+            radius: 2,
+            sigma: None,
+        }
+        .apply(half_transparent)
+        .to_rgba8();
+        let edge = blurred.get_pixel(2, 0);
+        assert_eq!([255, 255, 255], [edge[0], edge[1], edge[2]]);
+        assert!(edge[3] > 0 && edge[3] < 255);
+    }
+
+    #[test]
+    fn test_sharpen() {
+        let step = DynamicImage::ImageRgb8(image::RgbImage::from_fn(6, 1, |x, _| {
+            image::Rgb(if x < 3 {
+                [100, 100, 100]
+            } else {
+                [150, 150, 150]
+            })
+        }));
+        for luminance_only in [false, true] {
+            let sharpened = Filter::Sharpen {
+                amount: Decimal::from_hundredths(100),
+                radius: Decimal::from_hundredths(100),
+                luminance_only,
+            }
+            .apply(step.clone())
+            .to_rgb8();
+            // The step gets steeper, away from it nothing changes
+            assert!(sharpened.get_pixel(2, 0)[0] < 100);
+            assert!(sharpened.get_pixel(3, 0)[0] > 150);
+            assert_eq!(&image::Rgb([100, 100, 100]), sharpened.get_pixel(0, 0));
+        }
+    }
+
+    #[test]
+    fn test_noise() {
+        let grey = DynamicImage::ImageRgb8(image::RgbImage::from_pixel(
+            8,
+            8,
+            image::Rgb([128, 128, 128]),
+        ));
+        let noisy = Filter::Noise(20).apply(grey.clone()).to_rgb8();
+        assert!(noisy.pixels().any(|pixel| pixel.0 != [128, 128, 128]));
+        assert!(noisy
+            .pixels()
+            .flat_map(|pixel| pixel.0)
+            .all(|value| (108..=148).contains(&value)));
+        // The same every time, so that processed images can be reproduced
+        assert_eq!(noisy, Filter::Noise(20).apply(grey).to_rgb8());
+    }
+
+    #[test]
+    fn test_convolution() {
+        let image = DynamicImage::ImageRgba8(image::RgbaImage::from_fn(3, 3, |x, y| {
+            image::Rgba([(x * 50) as u8, (y * 50) as u8, 0, 200])
+        }));
+        let convolution = |weights: &[i32], columns, normalize| Filter::Convolution {
+            matrix: weights
+                .iter()
+                .map(|weight| Decimal::from_hundredths(weight * 100))
+                .collect(),
+            columns,
+            normalize,
+        };
+
+        let identity = convolution(&[0, 0, 0, 0, 1, 0, 0, 0, 0], 3, false);
+        assert_eq!(image.to_rgba8(), identity.apply(image.clone()).to_rgba8());
+
+        // Averaging a row keeps alpha as it is
+        let averaged = convolution(&[1, 1, 1], 3, true).apply(image).to_rgba8();
+        assert_eq!(&image::Rgba([50, 0, 0, 200]), averaged.get_pixel(1, 0));
+        assert_eq!(&image::Rgba([17, 50, 0, 200]), averaged.get_pixel(0, 1));
+    }
+}
@@ -0,0 +1,131 @@
+use image::{DynamicImage, RgbImage, RgbaImage};
+use libheif_rs::{ColorSpace, DecodingOptions, HeifContext, HeifError, LibHeif, RgbChroma};
+
+use super::image_encoding::ImageMetadata;
+
+const HEIC_MIME_TYPES: &[&str] = &["image/heic", "image/heif"];
+const HEIC_EXTENSIONS: &[&str] = &[".heic", ".heif"];
+// ISOBMFF brands that mean HEVC coded images, as opposed to e.g. AVIF's
+const HEIC_BRANDS: &[&[u8]] = &[b"heic", b"heix", b"heim", b"heis", b"hevc", b"hevx"];
+
+/// Whether the source is HEIC/HEIF, going by its content type, extension or (failing those) the
+/// brands in its `ftyp` box
+pub fn is_heic(bytes: &[u8], content_type: Option<&str>, image_url: &str) -> bool {
+    if content_type.is_some_and(|t| HEIC_MIME_TYPES.iter().any(|m| t.starts_with(m))) {
+        return true;
+    }
+    let path = image_url
+        .split(['?', '#'])
+        .next()
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    if HEIC_EXTENSIONS.iter().any(|e| path.ends_with(e)) {
+        return true;
+    }
+    // Box size, "ftyp", major brand, minor version, then the compatible brands
+    if bytes.len() < 16 || &bytes[4..8] != b"ftyp" {
+        return false;
+    }
+    let box_size = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize;
+    let ftyp = &bytes[..box_size.clamp(16, bytes.len())];
+    std::iter::once(&ftyp[8..12])
+        .chain(ftyp[16..].chunks_exact(4))
+        .any(|brand| HEIC_BRANDS.contains(&brand))
+}
+
+/// Decodes the primary image, turned the way the file says (unless told not to), along with its
+/// metadata. Unlike JPEG's, HEIC's rotation and mirroring isn't in the EXIF, so it's handled here.
+pub fn decode_heic(
+    bytes: &[u8],
+    autorotate: bool,
+) -> Result<(DynamicImage, ImageMetadata), HeifError> {
+    let context = HeifContext::read_from_bytes(bytes)?;
+    let handle = context.primary_image_handle()?;
+    let has_alpha = handle.has_alpha_channel();
+    let chroma = if has_alpha {
+        RgbChroma::Rgba
+    } else {
+        RgbChroma::Rgb
+    };
+    let mut options = DecodingOptions::new();
+    if let Some(options) = options.as_mut() {
+        options.set_ignore_transformations(!autorotate);
+    }
+    let decoded = LibHeif::new().decode(&handle, ColorSpace::Rgb(chroma), options)?;
+
+    let (width, height) = (decoded.width(), decoded.height());
+    let channels = if has_alpha { 4 } else { 3 };
+    let planes = decoded.planes();
+    let image = planes.interleaved.and_then(|plane| {
+        // Rows can be padded, so only the pixels of each row are copied over
+        let row_length = width as usize * channels;
+        let pixels: Vec<u8> = plane
+            .data
+            .chunks(plane.stride)
+            .take(height as usize)
+            .flat_map(|row| &row[..row_length])
+            .copied()
+            .collect();
+        if has_alpha {
+            RgbaImage::from_raw(width, height, pixels).map(DynamicImage::ImageRgba8)
+        } else {
+            RgbImage::from_raw(width, height, pixels).map(DynamicImage::ImageRgb8)
+        }
+    });
+    let Some(image) = image else {
+        return Err(HeifError {
+            code: libheif_rs::HeifErrorCode::DecoderPluginError,
+            sub_code: libheif_rs::HeifErrorSubCode::Unspecified,
+            message: "Decoded image has no interleaved RGB(A) plane".to_string(),
+        });
+    };
+
+    let mut exif_ids = [0; 1];
+    let exif = if handle.metadata_block_ids(&mut exif_ids, b"Exif") > 0 {
+        handle
+            .metadata(exif_ids[0])
+            .ok()
+            .and_then(|block| without_tiff_header_offset(&block))
+    } else {
+        None
+    };
+    let metadata = ImageMetadata {
+        icc_profile: handle.color_profile_raw().map(|profile| profile.data),
+        exif,
+    };
+    Ok((image, metadata))
+}
+
+// HEIF EXIF blocks start with the offset of the TIFF header, rather than the header itself
+fn without_tiff_header_offset(block: &[u8]) -> Option<Vec<u8>> {
+    let offset = u32::from_be_bytes(block.get(..4)?.try_into().ok()?) as usize;
+    let mut exif = block.get(4 + offset..)?.to_vec();
+    // The orientation (if any) was already dealt with when decoding, or is meant to be ignored
+    let _ = image::metadata::Orientation::remove_from_exif_chunk(&mut exif);
+    Some(exif)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_heic() {
+        assert!(is_heic(b"", Some("image/heic"), "https://a.com/photo"));
+        assert!(is_heic(b"", None, "https://a.com/IMG_0001.HEIC"));
+        let iphone = b"\x00\x00\x00\x18ftypheic\x00\x00\x00\x00mif1heic";
+        assert!(is_heic(iphone, None, "https://a.com/photo"));
+        let avif = b"\x00\x00\x00\x1cftypavif\x00\x00\x00\x00avifmif1miaf";
+        assert!(!is_heic(avif, None, "https://a.com/photo"));
+    }
+
+    #[test]
+    fn test_without_tiff_header_offset() {
+        let block = b"\x00\x00\x00\x02xxMM\x00\x2a\x00\x00\x00\x08\x00\x00";
+        assert_eq!(
+            Some(b"MM\x00\x2a\x00\x00\x00\x08\x00\x00".to_vec()),
+            without_tiff_header_offset(block)
+        );
+        assert_eq!(None, without_tiff_header_offset(b"\x00\x00\x00\x09xx"));
+    }
+}
@@ -13,12 +13,19 @@ use tracing::instrument;
 
 use crate::api::requests::ImageResizePathParam;
 
+use super::config::IccHandling;
+use super::image_encoding::{EncoderSettings, OutputOptions};
 use super::image_manipulation::Operations;
 
 #[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone)]
 pub struct ImageResizeRequest {
     pub requested_image_url: String,
     pub operations: Operations,
+    pub output: OutputOptions,
+    // What the output options come to with the config filled in, along with how colours are
+    // handled, so that changing the config doesn't keep serving images processed under the old one
+    pub encoder: EncoderSettings,
+    pub icc_handling: IccHandling,
 }
 
 #[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone, Copy)]
@@ -233,6 +240,8 @@ mod tests {
     use tokio::sync::OnceCell;
 
     use super::*;
+    use crate::infra::image_encoding::OutputFormat;
+    use crate::infra::image_manipulation::{FocalRegion, ResizeOptions};
     use crate::test_utils::{s3_client, TestResult};
 
     // Bucket, static because we assume the app is passed a created one.
@@ -283,12 +292,105 @@ mod tests {
                 target_width: 100,
                 target_height: 100,
             })),
+            output: OutputOptions::default(),
+            encoder: EncoderSettings::default(),
+            icc_handling: IccHandling::default(),
         };
         let key = req.cache_key()?;
         assert!(key.0.len() < 1024);
         Ok(())
     }
 
+    #[test]
+    fn test_cache_key_differs_by_focal_region() -> TestResult<()> {
+        let req_with_focal = |left| ImageResizeRequest {
+            requested_image_url: "https://beachape.com/images/something.png".to_string(),
+            operations: Operations::build_with_options(
+                &Some(ImageResize {
+                    target_width: 100,
+                    target_height: 100,
+                }),
+                ResizeOptions {
+                    focal: Some(FocalRegion {
+                        left,
+                        top: 0,
+                        right: 50,
+                        bottom: 50,
+                    }),
+                    ..Default::default()
+                },
+            ),
+            output: OutputOptions::default(),
+            encoder: EncoderSettings::default(),
+            icc_handling: IccHandling::default(),
+        };
+        assert_ne!(
+            req_with_focal(0).cache_key()?.0,
+            req_with_focal(10).cache_key()?.0
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_cache_key_with_output_options() -> TestResult<()> {
+        let req_with_output = |output| ImageResizeRequest {
+            requested_image_url: "https://beachape.com/images/something.png".to_string(),
+            operations: Operations::build(&None),
+            output,
+            encoder: EncoderSettings::default(),
+            icc_handling: IccHandling::default(),
+        };
+        let default_req = req_with_output(OutputOptions::default());
+        assert_ne!(
+            default_req.cache_key()?.0,
+            req_with_output(OutputOptions {
+                format: Some(OutputFormat::WebP),
+                ..Default::default()
+            })
+            .cache_key()?
+            .0
+        );
+        assert_ne!(
+            default_req.cache_key()?.0,
+            req_with_output(OutputOptions {
+                strip_icc: true,
+                ..Default::default()
+            })
+            .cache_key()?
+            .0
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_cache_key_differs_by_config() -> TestResult<()> {
+        let req_with_config = |encoder, icc_handling| ImageResizeRequest {
+            requested_image_url: "https://beachape.com/images/something.png".to_string(),
+            operations: Operations::build(&None),
+            output: OutputOptions::default(),
+            encoder,
+            icc_handling,
+        };
+        let default_req = req_with_config(EncoderSettings::default(), IccHandling::Convert);
+        let lower_quality = EncoderSettings {
+            quality: 50,
+            ..Default::default()
+        };
+        assert_ne!(
+            default_req.cache_key()?.0,
+            req_with_config(lower_quality, IccHandling::Convert)
+                .cache_key()?
+                .0
+        );
+        assert_ne!(
+            default_req.cache_key()?.0,
+            req_with_config(EncoderSettings::default(), IccHandling::Embed)
+                .cache_key()?
+                .0
+        );
+        Ok(())
+    }
+
     #[test]
     fn test_metadata() -> TestResult<()> {
         let req = ImageResizedCacheRequest {
@@ -298,6 +400,9 @@ mod tests {
                     target_width: 100,
                     target_height: 200,
                 })),
+                output: OutputOptions::default(),
+                encoder: EncoderSettings::default(),
+                icc_handling: IccHandling::default(),
             },
             content_type: "image/png".to_string(),
         };
@@ -326,6 +431,9 @@ mod tests {
                 target_width: 100,
                 target_height: 100,
             })),
+            output: OutputOptions::default(),
+            encoder: EncoderSettings::default(),
+            icc_handling: IccHandling::default(),
         };
         let retrieved = s3_image_cacher.get(&req).await;
         assert!(retrieved?.is_none());
@@ -347,6 +455,9 @@ mod tests {
                 target_width: 100,
                 target_height: 100,
             })),
+            output: OutputOptions::default(),
+            encoder: EncoderSettings::default(),
+            icc_handling: IccHandling::default(),
         };
         let content = b"testcontent";
         let image_set_req = ImageResizedCacheRequest {
@@ -377,6 +488,9 @@ mod tests {
                 target_width: 300,
                 target_height: 500,
             })),
+            output: OutputOptions::default(),
+            encoder: EncoderSettings::default(),
+            icc_handling: IccHandling::default(),
         };
         let content = b"testcontent";
         let image_set_req = ImageResizedCacheRequest {
@@ -405,7 +519,7 @@ mod tests {
         Ok(())
     }
 
-    static BUCKET_NAME: &'static str = "my-test-bucket";
+    static BUCKET_NAME: &str = "my-test-bucket";
     async fn s3_bucket() -> &'static String {
         S3_BUCKET
             .get_or_init(|| async {
@@ -0,0 +1,866 @@
+use std::io::Cursor;
+use std::str::FromStr;
+
+use image::codecs::{avif::AvifEncoder, png::PngEncoder};
+use image::error::{EncodingError, ImageFormatHint, LimitError, LimitErrorKind};
+use image::{DynamicImage, ImageEncoder, ImageError, ImageFormat};
+use serde::{Deserialize, Serialize};
+
+use super::config::OutputSettings;
+
+/// Formats that processed images can be written out as
+#[derive(PartialEq, Eq, Debug, Serialize, Deserialize, Clone, Copy)]
+pub enum OutputFormat {
+    Jpeg,
+    Png,
+    Gif,
+    WebP,
+    Avif,
+}
+
+impl OutputFormat {
+    pub fn name(&self) -> &'static str {
+        match self {
+            OutputFormat::Jpeg => "jpeg",
+            OutputFormat::Png => "png",
+            OutputFormat::Gif => "gif",
+            OutputFormat::WebP => "webp",
+            OutputFormat::Avif => "avif",
+        }
+    }
+
+    pub fn image_format(&self) -> ImageFormat {
+        match self {
+            OutputFormat::Jpeg => ImageFormat::Jpeg,
+            OutputFormat::Png => ImageFormat::Png,
+            OutputFormat::Gif => ImageFormat::Gif,
+            OutputFormat::WebP => ImageFormat::WebP,
+            OutputFormat::Avif => ImageFormat::Avif,
+        }
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "jpeg" | "jpg" => Ok(OutputFormat::Jpeg),
+            "png" => Ok(OutputFormat::Png),
+            "gif" => Ok(OutputFormat::Gif),
+            "webp" => Ok(OutputFormat::WebP),
+            "avif" => Ok(OutputFormat::Avif),
+            _ => anyhow::bail!(
+                "Unsupported output format [{s}], must be one of webp, avif, png, jpeg or gif"
+            ),
+        }
+    }
+}
+
+/// How the processed image gets written out. Part of the processed image cache key.
+#[derive(PartialEq, Eq, Debug, Serialize, Deserialize, Clone, Copy, Default)]
+pub struct OutputOptions {
+    // None keeps the format of the source image
+    pub format: Option<OutputFormat>,
+    // None means the configured default, 1 (worst) to 100 (best)
+    pub quality: Option<u8>,
+    // Only affects WebP, None means the configured default
+    pub lossless: Option<bool>,
+    // Drop the source's EXIF/ICC profile even if config says to keep it
+    pub strip_exif: bool,
+    pub strip_icc: bool,
+    // Only affects JPEG, None means the configured default
+    pub progressive: Option<bool>,
+    // Only affects JPEG and PNG, None means the configured default
+    pub optimise: Option<bool>,
+    // Lower the quality (and then the size) until the output is no bigger than this
+    pub max_bytes: Option<u32>,
+}
+
+impl OutputOptions {
+    pub fn image_format(&self, source_format: ImageFormat) -> ImageFormat {
+        self.format
+            .map(|f| f.image_format())
+            .unwrap_or(source_format)
+    }
+}
+
+/// Picks the best format the client says it can handle (per its Accept header), out of the
+/// ones turned on in settings. None means sticking to the source format.
+pub fn negotiate_format(settings: &OutputSettings, accept: &str) -> Option<OutputFormat> {
+    if settings.auto_avif && accepts(accept, "image/avif") {
+        Some(OutputFormat::Avif)
+    } else if settings.auto_webp && accepts(accept, "image/webp") {
+        Some(OutputFormat::WebP)
+    } else {
+        None
+    }
+}
+
+// Whether the media type is listed in the Accept header without being refused with q=0
+fn accepts(accept: &str, media_type: &str) -> bool {
+    accept.split(',').any(|entry| {
+        let mut parts = entry.split(';').map(str::trim);
+        let listed = parts
+            .next()
+            .is_some_and(|t| t.eq_ignore_ascii_case(media_type));
+        let refused = parts.any(|param| {
+            param
+                .strip_prefix("q=")
+                .and_then(|q| q.parse::<f32>().ok())
+                .is_some_and(|q| q <= 0.0)
+        });
+        listed && !refused
+    })
+}
+
+/// What the encoders get to work with, once the gaps in the requested output options have been
+/// filled in from config
+#[derive(PartialEq, Eq, Debug, Serialize, Deserialize, Clone, Copy)]
+pub struct EncoderSettings {
+    pub quality: u8,
+    pub avif_quality: u8,
+    pub avif_speed: u8,
+    pub webp_lossless: bool,
+    pub keep_exif: bool,
+    pub keep_icc: bool,
+    pub progressive: bool,
+    pub optimise: bool,
+}
+
+impl EncoderSettings {
+    pub fn resolve(output: &OutputOptions, settings: &OutputSettings) -> Self {
+        EncoderSettings {
+            quality: output.quality.unwrap_or(settings.default_quality),
+            avif_quality: output
+                .quality
+                .or(settings.avif_quality)
+                .unwrap_or(settings.default_quality),
+            avif_speed: settings.avif_speed.clamp(1, 10),
+            webp_lossless: output.lossless.unwrap_or(settings.webp_lossless),
+            keep_exif: settings.keep_exif && !output.strip_exif,
+            keep_icc: settings.keep_icc && !output.strip_icc,
+            progressive: output.progressive.unwrap_or(settings.progressive_jpeg),
+            optimise: output.optimise.unwrap_or(settings.optimise_output),
+        }
+    }
+}
+
+impl Default for EncoderSettings {
+    fn default() -> Self {
+        Self::resolve(&OutputOptions::default(), &OutputSettings::default())
+    }
+}
+
+/// Metadata read from the source image, that may get carried over to the output
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct ImageMetadata {
+    pub icc_profile: Option<Vec<u8>>,
+    // The raw EXIF (TIFF) block, without any container specific header
+    pub exif: Option<Vec<u8>>,
+}
+
+impl ImageMetadata {
+    /// Only what the settings say to keep
+    pub fn kept(&self, settings: &EncoderSettings) -> ImageMetadata {
+        ImageMetadata {
+            icc_profile: self.icc_profile.clone().filter(|_| settings.keep_icc),
+            exif: self.exif.clone().filter(|_| settings.keep_exif),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.icc_profile.is_none() && self.exif.is_none()
+    }
+}
+
+// Below this, max_bytes(n) shrinks the image rather than making the artefacts even worse
+const MIN_TARGET_QUALITY: u8 = 20;
+
+/// Encodes until the output is no bigger than max_bytes or max_attempts encodes have been done,
+/// lowering the quality first (for formats that have one) and then the dimensions. The callback
+/// gets the settings to encode with and the factor to scale the image down by.
+///
+/// If nothing fits, the smallest output is returned.
+pub fn encode_within(
+    max_bytes: usize,
+    max_attempts: u32,
+    format: ImageFormat,
+    settings: &EncoderSettings,
+    mut encode: impl FnMut(&EncoderSettings, f32) -> Result<Vec<u8>, ImageError>,
+) -> Result<Vec<u8>, ImageError> {
+    // Optimising (e.g. PNGs with oxipng) is too slow to do on every attempt, and only ever
+    // makes the output smaller, so the attempts go without and the one picked gets it, as the
+    // last of the attempts. With only the one attempt, that's optimised to begin with.
+    if !settings.optimise || max_attempts <= 1 {
+        return Ok(pick_within(max_bytes, max_attempts, format, settings, &mut encode)?.0);
+    }
+    let unoptimised = EncoderSettings {
+        optimise: false,
+        ..*settings
+    };
+    let (picked, picked_settings, scale) = pick_within(
+        max_bytes,
+        max_attempts - 1,
+        format,
+        &unoptimised,
+        &mut encode,
+    )?;
+    let optimised = encode(
+        &EncoderSettings {
+            optimise: true,
+            ..picked_settings
+        },
+        scale,
+    )?;
+    Ok(if optimised.len() < picked.len() {
+        optimised
+    } else {
+        picked
+    })
+}
+
+// The output encode_within goes with, along with the settings and scale it was encoded with
+fn pick_within(
+    max_bytes: usize,
+    max_attempts: u32,
+    format: ImageFormat,
+    settings: &EncoderSettings,
+    encode: &mut impl FnMut(&EncoderSettings, f32) -> Result<Vec<u8>, ImageError>,
+) -> Result<(Vec<u8>, EncoderSettings, f32), ImageError> {
+    let mut settings = *settings;
+    let mut smallest = (encode(&settings, 1.0)?, settings, 1.0);
+    let mut attempts = 1;
+    if smallest.0.len() <= max_bytes {
+        return Ok(smallest);
+    }
+
+    let (has_quality, quality) = match format {
+        ImageFormat::Jpeg => (true, settings.quality),
+        ImageFormat::WebP => (!settings.webp_lossless, settings.quality),
+        ImageFormat::Avif => (true, settings.avif_quality),
+        _ => (false, 0),
+    };
+    let with_quality = move |quality| EncoderSettings {
+        quality,
+        avif_quality: quality,
+        ..settings
+    };
+    if has_quality && quality > MIN_TARGET_QUALITY && attempts < max_attempts {
+        settings = with_quality(MIN_TARGET_QUALITY);
+        let lowest = encode(&settings, 1.0)?;
+        attempts += 1;
+        if lowest.len() <= max_bytes {
+            // Then the highest quality in between that still fits
+            let mut fitting = (lowest, settings, 1.0);
+            let (mut low, mut high) = (MIN_TARGET_QUALITY + 1, quality - 1);
+            while low <= high && attempts < max_attempts {
+                let middle = low + (high - low) / 2;
+                let encoded = encode(&with_quality(middle), 1.0)?;
+                attempts += 1;
+                if encoded.len() <= max_bytes {
+                    fitting = (encoded, with_quality(middle), 1.0);
+                    low = middle + 1;
+                } else {
+                    high = middle - 1;
+                }
+            }
+            return Ok(fitting);
+        }
+        smallest = (lowest, settings, 1.0);
+    }
+
+    // Bytes go roughly with pixels, so each side gets scaled by the square root of how far over
+    // the last attempt was, and a bit more to make it likelier to fit
+    let mut scale = 1.0;
+    let mut last_size = smallest.0.len();
+    while attempts < max_attempts {
+        scale *= ((max_bytes as f32 / last_size as f32).sqrt() * 0.9).min(0.9);
+        let encoded = encode(&settings, scale)?;
+        attempts += 1;
+        if encoded.len() <= max_bytes {
+            return Ok((encoded, settings, scale));
+        }
+        last_size = encoded.len();
+        if encoded.len() < smallest.0.len() {
+            smallest = (encoded, settings, scale);
+        }
+    }
+    Ok(smallest)
+}
+
+/// Encodes without any metadata
+pub fn encode(
+    image: &DynamicImage,
+    format: ImageFormat,
+    settings: &EncoderSettings,
+) -> Result<Vec<u8>, ImageError> {
+    encode_with_metadata(image, format, settings, &ImageMetadata::default())
+}
+
+/// Encodes with whatever of the metadata the settings say to keep, as far as the format allows
+pub fn encode_with_metadata(
+    image: &DynamicImage,
+    format: ImageFormat,
+    settings: &EncoderSettings,
+    metadata: &ImageMetadata,
+) -> Result<Vec<u8>, ImageError> {
+    let metadata = metadata.kept(settings);
+    let mut cursor = Cursor::new(Vec::new());
+    match format {
+        ImageFormat::Jpeg => return encode_jpeg(image, settings, &metadata),
+        ImageFormat::Png => {
+            let mut encoder = PngEncoder::new(&mut cursor);
+            add_metadata(&mut encoder, &metadata);
+            image.write_with_encoder(encoder)?;
+            if settings.optimise {
+                return optimise_png(cursor.get_ref());
+            }
+        }
+        ImageFormat::Avif => {
+            let mut encoder = AvifEncoder::new_with_speed_quality(
+                &mut cursor,
+                settings.avif_speed,
+                settings.avif_quality,
+            );
+            add_metadata(&mut encoder, &metadata);
+            image.write_with_encoder(encoder)?
+        }
+        // The built-in WebP encoder is lossless only, so libwebp is used instead
+        ImageFormat::WebP => {
+            let encoded = encode_webp(image, settings)?;
+            return Ok(add_webp_metadata(encoded, image, &metadata));
+        }
+        _ => image.write_to(&mut cursor, format)?,
+    }
+    Ok(cursor.into_inner())
+}
+
+// Encoders that can't carry some of the metadata (e.g. ICC profiles in AVIF) just go without
+fn add_metadata(encoder: &mut impl ImageEncoder, metadata: &ImageMetadata) {
+    if let Some(icc_profile) = &metadata.icc_profile {
+        let _ = encoder.set_icc_profile(icc_profile.clone());
+    }
+    if let Some(exif) = &metadata.exif {
+        let _ = encoder.set_exif_metadata(exif.clone());
+    }
+}
+
+// Rewrites the PNG as small as it can be without changing any pixels: in the smallest colour
+// type and bit depth (a palette included) that holds them, with the best filters and compression.
+// The metadata chunks are kept, as they have already been picked.
+fn optimise_png(png: &[u8]) -> Result<Vec<u8>, ImageError> {
+    oxipng::optimize_from_memory(png, &oxipng::Options::default()).map_err(|e| {
+        ImageError::Encoding(EncodingError::new(
+            ImageFormatHint::Exact(ImageFormat::Png),
+            e,
+        ))
+    })
+}
+
+// The built-in JPEG encoder can't write progressive JPEGs or make Huffman tables for the image,
+// so jpeg-encoder is used instead
+fn encode_jpeg(
+    image: &DynamicImage,
+    settings: &EncoderSettings,
+    metadata: &ImageMetadata,
+) -> Result<Vec<u8>, ImageError> {
+    let (Ok(width), Ok(height)) = (u16::try_from(image.width()), u16::try_from(image.height()))
+    else {
+        return Err(ImageError::Limits(LimitError::from_kind(
+            LimitErrorKind::DimensionError,
+        )));
+    };
+    let mut jpeg = Vec::new();
+    let mut encoder = jpeg_encoder::Encoder::new(&mut jpeg, settings.quality);
+    encoder.set_progressive(settings.progressive);
+    encoder.set_optimized_huffman_tables(settings.optimise);
+    // Like with the other encoders, metadata that doesn't fit is left out
+    if let Some(icc_profile) = &metadata.icc_profile {
+        let _ = encoder.add_icc_profile(icc_profile);
+    }
+    if let Some(exif) = &metadata.exif {
+        let _ = encoder.add_exif_metadata(exif);
+    }
+    let encoded = match image {
+        DynamicImage::ImageLuma8(luma) => {
+            encoder.encode(luma, width, height, jpeg_encoder::ColorType::Luma)
+        }
+        DynamicImage::ImageRgb8(rgb) => {
+            encoder.encode(rgb, width, height, jpeg_encoder::ColorType::Rgb)
+        }
+        // JPEG only does 8-bit greyscale or RGB, so e.g. alpha has to go before encoding
+        _ => encoder.encode(
+            &image.to_rgb8(),
+            width,
+            height,
+            jpeg_encoder::ColorType::Rgb,
+        ),
+    };
+    encoded.map_err(|e| {
+        ImageError::Encoding(EncodingError::new(
+            ImageFormatHint::Exact(ImageFormat::Jpeg),
+            e,
+        ))
+    })?;
+    Ok(jpeg)
+}
+
+const VP8X_ICC_FLAG: u8 = 0x20;
+const VP8X_ALPHA_FLAG: u8 = 0x10;
+const VP8X_EXIF_FLAG: u8 = 0x08;
+
+// libwebp's simple encoding API doesn't do metadata, so the chunks get spliced into what it
+// wrote, moving it over to the extended (VP8X) layout if it isn't already
+fn add_webp_metadata(webp: Vec<u8>, image: &DynamicImage, metadata: &ImageMetadata) -> Vec<u8> {
+    // RIFF header, then the chunks
+    const HEADER_LENGTH: usize = 12;
+    if metadata.is_empty() || webp.len() < HEADER_LENGTH {
+        return webp;
+    }
+    let chunks = &webp[HEADER_LENGTH..];
+    let (flags, canvas, image_chunks) = if chunks.starts_with(b"VP8X") && chunks.len() >= 18 {
+        // Flags, 3 reserved bytes, then 24-bit width - 1 and height - 1
+        (chunks[8], chunks[12..18].to_vec(), &chunks[18..])
+    } else {
+        let flags = if image.color().has_alpha() {
+            VP8X_ALPHA_FLAG
+        } else {
+            0
+        };
+        let mut canvas = (image.width() - 1).to_le_bytes()[..3].to_vec();
+        canvas.extend_from_slice(&(image.height() - 1).to_le_bytes()[..3]);
+        (flags, canvas, chunks)
+    };
+
+    let mut vp8x = vec![flags, 0, 0, 0];
+    if metadata.icc_profile.is_some() {
+        vp8x[0] |= VP8X_ICC_FLAG;
+    }
+    if metadata.exif.is_some() {
+        vp8x[0] |= VP8X_EXIF_FLAG;
+    }
+    vp8x.extend_from_slice(&canvas);
+
+    let mut body = b"WEBP".to_vec();
+    write_riff_chunk(&mut body, b"VP8X", &vp8x);
+    if let Some(icc_profile) = &metadata.icc_profile {
+        write_riff_chunk(&mut body, b"ICCP", icc_profile);
+    }
+    body.extend_from_slice(image_chunks);
+    if let Some(exif) = &metadata.exif {
+        write_riff_chunk(&mut body, b"EXIF", exif);
+    }
+
+    let mut riff = b"RIFF".to_vec();
+    riff.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    riff.extend_from_slice(&body);
+    riff
+}
+
+fn write_riff_chunk(out: &mut Vec<u8>, fourcc: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(fourcc);
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out.extend_from_slice(data);
+    // Chunks are padded to an even length
+    if data.len() % 2 == 1 {
+        out.push(0);
+    }
+}
+
+fn encode_webp(image: &DynamicImage, settings: &EncoderSettings) -> Result<Vec<u8>, ImageError> {
+    let (width, height) = (image.width(), image.height());
+    let quality = settings.quality as f32;
+    let encoded = if image.color().has_alpha() {
+        let rgba = image.to_rgba8();
+        webp::Encoder::from_rgba(&rgba, width, height)
+            .encode_simple(settings.webp_lossless, quality)
+            .map(|memory| memory.to_vec())
+    } else {
+        let rgb = image.to_rgb8();
+        webp::Encoder::from_rgb(&rgb, width, height)
+            .encode_simple(settings.webp_lossless, quality)
+            .map(|memory| memory.to_vec())
+    };
+    encoded.map_err(|e| {
+        ImageError::Encoding(EncodingError::new(
+            ImageFormatHint::Exact(ImageFormat::WebP),
+            format!("{e:?}"),
+        ))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use image::ImageDecoder;
+
+    use super::*;
+
+    #[test]
+    fn test_output_format_from_str() {
+        assert_eq!(OutputFormat::WebP, "webp".parse().unwrap());
+        assert_eq!(OutputFormat::Jpeg, "jpg".parse().unwrap());
+        assert!("bmp".parse::<OutputFormat>().is_err());
+    }
+
+    #[test]
+    fn test_negotiate_format() {
+        let both = OutputSettings {
+            auto_webp: true,
+            auto_avif: true,
+            ..Default::default()
+        };
+        let chrome = "image/avif,image/webp,image/apng,image/svg+xml,image/*,*/*;q=0.8";
+        assert_eq!(Some(OutputFormat::Avif), negotiate_format(&both, chrome));
+        assert_eq!(
+            Some(OutputFormat::WebP),
+            negotiate_format(&both, "image/webp, */*;q=0.5")
+        );
+        assert_eq!(None, negotiate_format(&both, "image/png,image/*;q=0.8"));
+        assert_eq!(None, negotiate_format(&both, "image/webp;q=0"));
+        assert_eq!(None, negotiate_format(&both, ""));
+
+        let webp_only = OutputSettings {
+            auto_webp: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            Some(OutputFormat::WebP),
+            negotiate_format(&webp_only, chrome)
+        );
+        assert_eq!(None, negotiate_format(&OutputSettings::default(), chrome));
+    }
+
+    #[test]
+    fn test_encode_to_every_output_format() {
+        let image = DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(
+            4,
+            4,
+            image::Rgba([255, 0, 0, 128]),
+        ));
+        for output_format in [
+            OutputFormat::Jpeg,
+            OutputFormat::Png,
+            OutputFormat::Gif,
+            OutputFormat::WebP,
+            OutputFormat::Avif,
+        ] {
+            let format = output_format.image_format();
+            let bytes = encode(&image, format, &EncoderSettings::default()).unwrap();
+            assert_eq!(
+                format,
+                image::guess_format(&bytes).unwrap(),
+                "{output_format:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_encode_with_quality() {
+        let image = DynamicImage::ImageRgb8(image::RgbImage::from_fn(64, 64, |x, y| {
+            image::Rgb([(x * 4) as u8, (y * 4) as u8, ((x * y) % 256) as u8])
+        }));
+        for format in [ImageFormat::Jpeg, ImageFormat::WebP, ImageFormat::Avif] {
+            let with_quality = move |quality| EncoderSettings {
+                quality,
+                avif_quality: quality,
+                ..Default::default()
+            };
+            let low = encode(&image, format, &with_quality(10)).unwrap();
+            let high = encode(&image, format, &with_quality(95)).unwrap();
+            assert!(low.len() < high.len(), "{format:?}");
+        }
+    }
+
+    #[test]
+    fn test_encoder_settings_resolve() {
+        let settings = OutputSettings {
+            default_quality: 70,
+            ..Default::default()
+        };
+        let resolved = EncoderSettings::resolve(&OutputOptions::default(), &settings);
+        assert_eq!((70, 70), (resolved.quality, resolved.avif_quality));
+
+        let with_avif_quality = OutputSettings {
+            avif_quality: Some(50),
+            avif_speed: 20,
+            ..settings
+        };
+        let resolved = EncoderSettings::resolve(&OutputOptions::default(), &with_avif_quality);
+        assert_eq!(
+            (70, 50, 10),
+            (resolved.quality, resolved.avif_quality, resolved.avif_speed)
+        );
+
+        // Asking for a quality overrides both defaults
+        let output = OutputOptions {
+            quality: Some(30),
+            ..Default::default()
+        };
+        let resolved = EncoderSettings::resolve(&output, &with_avif_quality);
+        assert_eq!((30, 30), (resolved.quality, resolved.avif_quality));
+
+        // EXIF only survives when config keeps it, ICC unless the request strips it
+        let resolved = EncoderSettings::resolve(&OutputOptions::default(), &settings);
+        assert_eq!((false, true), (resolved.keep_exif, resolved.keep_icc));
+        let output = OutputOptions {
+            strip_icc: true,
+            ..Default::default()
+        };
+        let keeping_exif = OutputSettings {
+            keep_exif: true,
+            ..settings
+        };
+        let resolved = EncoderSettings::resolve(&output, &keeping_exif);
+        assert_eq!((true, false), (resolved.keep_exif, resolved.keep_icc));
+    }
+
+    #[test]
+    fn test_encode_with_metadata() {
+        let image = DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(
+            8,
+            8,
+            image::Rgba([10, 20, 30, 200]),
+        ));
+        let metadata = ImageMetadata {
+            icc_profile: Some(b"not really an icc profile".to_vec()),
+            exif: Some(b"MM\0\x2a\0\0\0\x08\0\0\0\0\0\0".to_vec()),
+        };
+        let keep_both = EncoderSettings {
+            keep_exif: true,
+            keep_icc: true,
+            ..Default::default()
+        };
+        for format in [ImageFormat::Jpeg, ImageFormat::Png, ImageFormat::WebP] {
+            let read = |bytes: &[u8]| {
+                let mut reader = image::ImageReader::new(Cursor::new(bytes));
+                reader.set_format(format);
+                let mut decoder = reader.into_decoder().unwrap();
+                let found = ImageMetadata {
+                    icc_profile: decoder.icc_profile().unwrap(),
+                    exif: decoder.exif_metadata().unwrap(),
+                };
+                // Still a valid image
+                DynamicImage::from_decoder(decoder).unwrap();
+                found
+            };
+
+            let bytes = encode_with_metadata(&image, format, &keep_both, &metadata).unwrap();
+            assert_eq!(metadata, read(&bytes), "{format:?}");
+
+            let stripped = EncoderSettings {
+                keep_exif: false,
+                ..keep_both
+            };
+            let bytes = encode_with_metadata(&image, format, &stripped, &metadata).unwrap();
+            let expected = ImageMetadata {
+                exif: None,
+                ..metadata.clone()
+            };
+            assert_eq!(expected, read(&bytes), "{format:?}");
+        }
+    }
+
+    #[test]
+    fn test_encode_webp_lossless() {
+        let image = DynamicImage::ImageRgba8(image::RgbaImage::from_fn(32, 32, |x, y| {
+            image::Rgba([(x * 8) as u8, (y * 8) as u8, 100, 200])
+        }));
+        let lossless = EncoderSettings {
+            webp_lossless: true,
+            ..Default::default()
+        };
+        let bytes = encode(&image, ImageFormat::WebP, &lossless).unwrap();
+        let decoded = image::load_from_memory_with_format(&bytes, ImageFormat::WebP).unwrap();
+        assert_eq!(image.to_rgba8(), decoded.to_rgba8());
+
+        let lossy = encode(&image, ImageFormat::WebP, &EncoderSettings::default()).unwrap();
+        let decoded = image::load_from_memory_with_format(&lossy, ImageFormat::WebP).unwrap();
+        assert_eq!((32, 32), (decoded.width(), decoded.height()));
+        assert_ne!(bytes, lossy);
+    }
+
+    #[test]
+    fn test_encode_progressive_and_optimised() {
+        let image = DynamicImage::ImageRgb8(image::RgbImage::from_fn(64, 48, |x, y| {
+            image::Rgb([(x * 4) as u8, (y * 5) as u8, ((x * y) % 200) as u8])
+        }));
+        let plain = encode(&image, ImageFormat::Jpeg, &EncoderSettings::default()).unwrap();
+        let optimised = EncoderSettings {
+            optimise: true,
+            ..Default::default()
+        };
+        let bytes = encode(&image, ImageFormat::Jpeg, &optimised).unwrap();
+        assert!(bytes.len() < plain.len());
+        let progressive = EncoderSettings {
+            progressive: true,
+            ..Default::default()
+        };
+        let bytes = encode(&image, ImageFormat::Jpeg, &progressive).unwrap();
+        // Start of frame for a progressive JPEG
+        assert!(bytes.windows(2).any(|w| w == [0xff, 0xc2]));
+        let read = |bytes: &[u8]| image::load_from_memory_with_format(bytes, ImageFormat::Jpeg);
+        assert_eq!(read(&plain).unwrap(), read(&bytes).unwrap());
+        // The decoder image uses misreads some baseline JPEGs with optimised tables (that libjpeg
+        // reads fine), so it's the progressive ones that show optimising changes no pixels
+        let both = EncoderSettings {
+            progressive: true,
+            optimise: true,
+            ..Default::default()
+        };
+        let optimised_bytes = encode(&image, ImageFormat::Jpeg, &both).unwrap();
+        assert!(optimised_bytes.len() < bytes.len());
+        assert_eq!(read(&plain).unwrap(), read(&optimised_bytes).unwrap());
+
+        let plain = encode(&image, ImageFormat::Png, &EncoderSettings::default()).unwrap();
+        let bytes = encode(&image, ImageFormat::Png, &optimised).unwrap();
+        assert!(bytes.len() < plain.len());
+        let decoded = image::load_from_memory_with_format(&bytes, ImageFormat::Png).unwrap();
+        assert_eq!(image.to_rgb8(), decoded.to_rgb8());
+    }
+
+    #[test]
+    fn test_optimise_png() {
+        let optimise = EncoderSettings {
+            optimise: true,
+            keep_icc: true,
+            ..Default::default()
+        };
+        let plain = |image: &DynamicImage| {
+            encode(image, ImageFormat::Png, &EncoderSettings::default()).unwrap()
+        };
+        // The colour type and bit depth from the header, along with the pixels
+        let read = |png: &[u8]| {
+            let decoded = image::load_from_memory_with_format(png, ImageFormat::Png).unwrap();
+            (png[25], png[24], decoded)
+        };
+        const GREYSCALE: u8 = 0;
+        const RGB: u8 = 2;
+        const INDEXED: u8 = 3;
+
+        // Few enough colours for a palette
+        let colours = [[255, 0, 0, 255], [0, 0, 255, 128], [0, 255, 0, 255]];
+        let image = DynamicImage::ImageRgba8(image::RgbaImage::from_fn(50, 30, |x, y| {
+            image::Rgba(colours[((x / 7 + y / 5) % 3) as usize])
+        }));
+        let optimised = encode(&image, ImageFormat::Png, &optimise).unwrap();
+        assert!(optimised.len() < plain(&image).len());
+        let (colour_type, bit_depth, decoded) = read(&optimised);
+        assert_eq!((INDEXED, 2), (colour_type, bit_depth));
+        assert_eq!(image.to_rgba8(), decoded.to_rgba8());
+
+        // Opaque greys only need the one channel
+        let image = DynamicImage::ImageRgba8(image::RgbaImage::from_fn(64, 64, |x, y| {
+            let v = (x * 4 + y) as u8;
+            image::Rgba([v, v, v, 255])
+        }));
+        let optimised = encode(&image, ImageFormat::Png, &optimise).unwrap();
+        assert!(optimised.len() < plain(&image).len());
+        let (colour_type, bit_depth, decoded) = read(&optimised);
+        assert_eq!((GREYSCALE, 8), (colour_type, bit_depth));
+        assert_eq!(image.to_rgba8(), decoded.to_rgba8());
+
+        // 16 bits are kept when they're used, and dropped when they're not
+        let image = DynamicImage::ImageRgb16(image::ImageBuffer::from_fn(20, 20, |x, y| {
+            image::Rgb([x as u16 * 1000 + 1, y as u16 * 3000, 7])
+        }));
+        let (colour_type, bit_depth, decoded) =
+            read(&encode(&image, ImageFormat::Png, &optimise).unwrap());
+        assert_eq!((RGB, 16), (colour_type, bit_depth));
+        assert_eq!(image, decoded);
+        let image = DynamicImage::ImageRgb16(image::ImageBuffer::from_fn(20, 20, |x, y| {
+            image::Rgb([x as u16 * 257, y as u16 * 257, 0])
+        }));
+        let (_, bit_depth, decoded) = read(&encode(&image, ImageFormat::Png, &optimise).unwrap());
+        assert_eq!(8, bit_depth);
+        assert_eq!(image.to_rgb8(), decoded.to_rgb8());
+
+        // The metadata is kept
+        let metadata = ImageMetadata {
+            icc_profile: Some(b"profile".to_vec()),
+            exif: None,
+        };
+        let optimised =
+            encode_with_metadata(&image, ImageFormat::Png, &optimise, &metadata).unwrap();
+        let mut decoder = image::codecs::png::PngDecoder::new(Cursor::new(optimised)).unwrap();
+        assert_eq!(metadata.icc_profile, decoder.icc_profile().unwrap());
+    }
+
+    #[test]
+    fn test_encode_within() {
+        let image = DynamicImage::ImageRgb8(image::RgbImage::from_fn(64, 64, |x, y| {
+            image::Rgb([(x * 4) as u8, ((x * y) % 256) as u8, (y * 31 % 256) as u8])
+        }));
+        let encode_scaled = |settings: &EncoderSettings, scale: f32, format| {
+            let size = (64.0 * scale) as u32;
+            let scaled = image.resize_exact(size, size, image::imageops::FilterType::Triangle);
+            encode(&scaled, format, settings)
+        };
+        let attempts = std::cell::RefCell::new(Vec::new());
+        let jpeg = |settings: &EncoderSettings, scale: f32| {
+            attempts.borrow_mut().push((settings.quality, scale));
+            encode_scaled(settings, scale, ImageFormat::Jpeg)
+        };
+        let settings = EncoderSettings::default();
+
+        // Lower quality gets it under the budget, with the highest quality that does
+        let full = jpeg(&settings, 1.0).unwrap().len();
+        let lowest_quality = EncoderSettings {
+            quality: 20,
+            ..settings
+        };
+        let lowest = jpeg(&lowest_quality, 1.0).unwrap().len();
+        let budget = (full + lowest) / 2;
+        attempts.borrow_mut().clear();
+        let bytes = encode_within(budget, 8, ImageFormat::Jpeg, &settings, jpeg).unwrap();
+        assert!(bytes.len() <= budget);
+        let tried = attempts.take();
+        assert_eq!(vec![(80, 1.0), (20, 1.0)], tried[..2]);
+        assert!(tried.iter().all(|(_, scale)| *scale == 1.0));
+        assert_eq!(8, tried.len());
+
+        // Then the image gets smaller
+        // (not by half, as the headers alone are a good part of a JPEG this small)
+        let budget = lowest * 3 / 4;
+        let bytes = encode_within(budget, 8, ImageFormat::Jpeg, &settings, jpeg).unwrap();
+        assert!(bytes.len() <= budget);
+        assert!(attempts.take().last().unwrap().1 < 1.0);
+
+        // Giving up after the attempts run out, with the smallest one
+        let bytes = encode_within(10, 3, ImageFormat::Jpeg, &settings, jpeg).unwrap();
+        assert_eq!(3, attempts.take().len());
+        assert!(bytes.len() > 10);
+
+        // Formats without a quality go straight to shrinking
+        let png = |settings: &EncoderSettings, scale: f32| {
+            encode_scaled(settings, scale, ImageFormat::Png)
+        };
+        let bytes = encode_within(4000, 8, ImageFormat::Png, &settings, png).unwrap();
+        assert!(bytes.len() <= 4000);
+
+        // Optimising only happens once, to what got picked
+        let optimised_attempts = std::cell::RefCell::new(Vec::new());
+        let png = |settings: &EncoderSettings, scale: f32| {
+            optimised_attempts
+                .borrow_mut()
+                .push((settings.optimise, scale));
+            encode_scaled(settings, scale, ImageFormat::Png)
+        };
+        let optimise = EncoderSettings {
+            optimise: true,
+            ..settings
+        };
+        let bytes = encode_within(4000, 8, ImageFormat::Png, &optimise, png).unwrap();
+        assert!(bytes.len() <= 4000);
+        let tried = optimised_attempts.take();
+        let (last, searched) = tried.split_last().unwrap();
+        assert!(searched.iter().all(|(optimise, _)| !optimise));
+        assert_eq!((true, searched.last().unwrap().1), *last);
+
+        // The optimised encode counts towards the attempts
+        encode_within(10, 3, ImageFormat::Png, &optimise, png).unwrap();
+        assert_eq!(3, optimised_attempts.take().len());
+        encode_within(10, 1, ImageFormat::Png, &optimise, png).unwrap();
+        assert_eq!(vec![(true, 1.0)], optimised_attempts.take());
+    }
+}
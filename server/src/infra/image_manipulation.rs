@@ -1,14 +1,322 @@
-use image::DynamicImage;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use image::{imageops, imageops::FilterType, ColorType, DynamicImage, Frame, Rgba32FImage};
 use serde::{Deserialize, Serialize};
 use tracing::instrument;
 
+use crate::api::requests::{
+    parse_coordinates, FitInPathParam, HorizontalAlignPathParam, ImageProcessingPath,
+    TrimFromPathParam, VerticalAlignPathParam,
+};
+
+use super::filters::{from_rgba32f, gaussian_blur, parse_filter, Decimal, Filter, ParsedFilters};
 use super::image_caching::ImageResize;
+use super::image_encoding::OutputOptions;
+use super::smart_crop::{detect_focal_point, FocalPoint};
+use super::validations::ValidationErrors;
 
-#[derive(PartialEq, Eq, Debug, Serialize, Deserialize, Clone, Copy)]
+#[derive(PartialEq, Eq, Debug, Serialize, Deserialize, Clone)]
 pub enum Operation {
-    Resize { width: u32, height: u32 },
+    Trim {
+        from: TrimFrom,
+        // Euclidean RGBA distance from the reference colour still considered part of the border
+        tolerance: u16,
+    },
+    Crop {
+        left: u32,
+        top: u32,
+        right: u32,
+        bottom: u32,
+    },
+    Resize {
+        width: u32,
+        height: u32,
+        mode: ResizeMode,
+        horizontal_align: HorizontalAlign,
+        vertical_align: VerticalAlign,
+        focal: Option<FocalRegion>,
+        // Use the detected focal point of the image (unless an explicit focal region is given)
+        smart: bool,
+    },
     FlipHorizontally,
     FlipVertically,
+    // Rotations are counter-clockwise, like Thumbor's
+    Rotate90,
+    Rotate180,
+    Rotate270,
+    // Any other angle, on a canvas grown to fit, with the corners it exposes filled
+    Rotate {
+        degrees: Decimal,
+        fill: Fill,
+    },
+    // Puts a fit-in result on a canvas the size of the box (flipped the way the adaptive modes
+    // flip it), filling around it. Sides of 0 are left as they are.
+    Pad {
+        width: u32,
+        height: u32,
+        adaptive: bool,
+        horizontal_align: HorizontalAlign,
+        vertical_align: VerticalAlign,
+        fill: Fill,
+    },
+    Filter(Filter),
+    // Keeps the pixels as stored, rather than turned the way the EXIF orientation says.
+    // Orientation is sorted out when decoding, so this only ever comes first.
+    NoAutorotate,
+}
+
+/// Which corner's colour is taken as the border colour to trim away
+#[derive(PartialEq, Eq, Debug, Serialize, Deserialize, Clone, Copy, Default)]
+pub enum TrimFrom {
+    #[default]
+    TopLeft,
+    BottomRight,
+}
+
+impl TrimFrom {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TrimFrom::TopLeft => "top-left",
+            TrimFrom::BottomRight => "bottom-right",
+        }
+    }
+}
+
+impl From<TrimFromPathParam> for TrimFrom {
+    fn from(value: TrimFromPathParam) -> Self {
+        match value {
+            TrimFromPathParam::TopLeft => TrimFrom::TopLeft,
+            TrimFromPathParam::BottomRight => TrimFrom::BottomRight,
+        }
+    }
+}
+
+/// How a resize deals with a target box whose aspect ratio differs from the source's.
+#[derive(PartialEq, Eq, Debug, Serialize, Deserialize, Clone, Copy, Default)]
+pub enum ResizeMode {
+    /// Scale until the box is covered, then crop whatever overflows (Thumbor's default)
+    #[default]
+    Cover,
+    /// Scale down until the whole image fits inside the box
+    FitIn,
+    /// Scale down until the image fits the box on its smallest side, so the other side may overflow
+    FullFitIn,
+    /// `FitIn`, but the box is flipped if that better matches the orientation of the image
+    AdaptiveFitIn,
+    /// `FullFitIn`, but the box is flipped if that better matches the orientation of the image
+    AdaptiveFullFitIn,
+    /// Scale each side independently to exactly match the box, ignoring the aspect ratio
+    Stretch,
+}
+
+impl ResizeMode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ResizeMode::Cover => "cover",
+            ResizeMode::FitIn => "fit-in",
+            ResizeMode::FullFitIn => "full-fit-in",
+            ResizeMode::AdaptiveFitIn => "adaptive-fit-in",
+            ResizeMode::AdaptiveFullFitIn => "adaptive-full-fit-in",
+            ResizeMode::Stretch => "stretch",
+        }
+    }
+}
+
+impl From<FitInPathParam> for ResizeMode {
+    fn from(value: FitInPathParam) -> Self {
+        match value {
+            FitInPathParam::FitIn => ResizeMode::FitIn,
+            FitInPathParam::FullFitIn => ResizeMode::FullFitIn,
+            FitInPathParam::AdaptiveFitIn => ResizeMode::AdaptiveFitIn,
+            FitInPathParam::AdaptiveFullFitIn => ResizeMode::AdaptiveFullFitIn,
+        }
+    }
+}
+
+/// Which part of the image to keep when a cover resize has to cut some of it off
+#[derive(PartialEq, Eq, Debug, Serialize, Deserialize, Clone, Copy, Default)]
+pub enum HorizontalAlign {
+    Left,
+    #[default]
+    Center,
+    Right,
+}
+
+impl HorizontalAlign {
+    pub fn name(&self) -> &'static str {
+        match self {
+            HorizontalAlign::Left => "left",
+            HorizontalAlign::Center => "center",
+            HorizontalAlign::Right => "right",
+        }
+    }
+}
+
+impl From<HorizontalAlignPathParam> for HorizontalAlign {
+    fn from(value: HorizontalAlignPathParam) -> Self {
+        match value {
+            HorizontalAlignPathParam::Left => HorizontalAlign::Left,
+            HorizontalAlignPathParam::Center => HorizontalAlign::Center,
+            HorizontalAlignPathParam::Right => HorizontalAlign::Right,
+        }
+    }
+}
+
+/// Which part of the image to keep when a cover resize has to cut some of it off
+#[derive(PartialEq, Eq, Debug, Serialize, Deserialize, Clone, Copy, Default)]
+pub enum VerticalAlign {
+    Top,
+    #[default]
+    Middle,
+    Bottom,
+}
+
+impl VerticalAlign {
+    pub fn name(&self) -> &'static str {
+        match self {
+            VerticalAlign::Top => "top",
+            VerticalAlign::Middle => "middle",
+            VerticalAlign::Bottom => "bottom",
+        }
+    }
+}
+
+impl From<VerticalAlignPathParam> for VerticalAlign {
+    fn from(value: VerticalAlignPathParam) -> Self {
+        match value {
+            VerticalAlignPathParam::Top => VerticalAlign::Top,
+            VerticalAlignPathParam::Middle => VerticalAlign::Middle,
+            VerticalAlignPathParam::Bottom => VerticalAlign::Bottom,
+        }
+    }
+}
+
+/// The part of the source image (in source pixels) that a cover resize should keep in view,
+/// taking precedence over alignment
+#[derive(PartialEq, Eq, Debug, Serialize, Deserialize, Clone, Copy)]
+pub struct FocalRegion {
+    pub left: u32,
+    pub top: u32,
+    pub right: u32,
+    pub bottom: u32,
+}
+
+impl FocalRegion {
+    fn center(&self) -> (f64, f64) {
+        (
+            (self.left as f64 + self.right as f64) / 2.0,
+            (self.top as f64 + self.bottom as f64) / 2.0,
+        )
+    }
+}
+
+impl FromStr for FocalRegion {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let maybe_region = s.split_once(':').and_then(|(top_left, bottom_right)| {
+            let (left, top) = parse_coordinates(top_left)?;
+            let (right, bottom) = parse_coordinates(bottom_right)?;
+            Some(FocalRegion {
+                left,
+                top,
+                right,
+                bottom,
+            })
+        });
+        maybe_region.ok_or_else(|| anyhow::anyhow!("Invalid focal region [{s}], expected AxB:CxD"))
+    }
+}
+
+/// An sRGB colour, with alpha
+#[derive(PartialEq, Eq, Debug, Serialize, Deserialize, Clone, Copy)]
+pub struct Colour(pub [u8; 4]);
+
+impl Colour {
+    /// As it would be written in the path
+    pub fn hex(&self) -> String {
+        let [r, g, b, a] = self.0;
+        if a == u8::MAX {
+            format!("{r:02x}{g:02x}{b:02x}")
+        } else {
+            format!("{r:02x}{g:02x}{b:02x}{a:02x}")
+        }
+    }
+}
+
+// Hex without the #, as RGB, RRGGBB or RRGGBBAA
+impl FromStr for Colour {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let digits: Option<Vec<u8>> = s.chars().map(|c| c.to_digit(16).map(|d| d as u8)).collect();
+        let colour = match digits.as_deref() {
+            Some(&[r, g, b]) => Some([r * 17, g * 17, b * 17, u8::MAX]),
+            Some(digits) if digits.len() == 6 || digits.len() == 8 => {
+                let mut channels = [u8::MAX; 4];
+                for (channel, pair) in channels.iter_mut().zip(digits.chunks(2)) {
+                    *channel = pair[0] * 16 + pair[1];
+                }
+                Some(channels)
+            }
+            _ => None,
+        };
+        colour
+            .map(Colour)
+            .ok_or_else(|| anyhow::anyhow!("Invalid colour [{s}], expected hex like ff0000"))
+    }
+}
+
+/// What fills the parts of the canvas that padding or rotating leave uncovered
+#[derive(PartialEq, Eq, Debug, Serialize, Deserialize, Clone, Copy, Default)]
+pub enum Fill {
+    Colour(Colour),
+    #[default]
+    Transparent,
+    // A blurred copy of the image, scaled up to cover the canvas
+    Blur,
+    // The most common colour along the edges of the image
+    Auto,
+}
+
+impl Fill {
+    /// As it would be written in the path
+    pub fn name(&self) -> String {
+        match self {
+            Fill::Colour(colour) => colour.hex(),
+            Fill::Transparent => "transparent".to_string(),
+            Fill::Blur => "blur".to_string(),
+            Fill::Auto => "auto".to_string(),
+        }
+    }
+}
+
+impl FromStr for Fill {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "transparent" => Ok(Fill::Transparent),
+            "blur" => Ok(Fill::Blur),
+            "auto" => Ok(Fill::Auto),
+            colour => colour.parse().map(Fill::Colour).map_err(|_| {
+                anyhow::anyhow!(
+                    "Invalid fill [{s}], expected a hex colour like ff0000, transparent, blur or auto"
+                )
+            }),
+        }
+    }
+}
+
+/// Everything about a resize apart from the target size
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub struct ResizeOptions {
+    pub mode: ResizeMode,
+    pub horizontal_align: HorizontalAlign,
+    pub vertical_align: VerticalAlign,
+    pub focal: Option<FocalRegion>,
+    pub smart: bool,
 }
 
 #[derive(PartialEq, Eq, Debug, Serialize, Deserialize, Clone)]
@@ -16,12 +324,21 @@ pub struct Operations(pub Vec<Operation>);
 
 impl Operations {
     pub fn build(image_resize: &Option<ImageResize>) -> Self {
+        Self::build_with_options(image_resize, ResizeOptions::default())
+    }
+
+    pub fn build_with_options(image_resize: &Option<ImageResize>, options: ResizeOptions) -> Self {
         let mut v = Vec::new();
 
         if let Some(image_resize) = image_resize {
             v.push(Operation::Resize {
                 width: image_resize.target_width.unsigned_abs(),
                 height: image_resize.target_height.unsigned_abs(),
+                mode: options.mode,
+                horizontal_align: options.horizontal_align,
+                vertical_align: options.vertical_align,
+                focal: options.focal,
+                smart: options.smart,
             });
             if image_resize.target_width.is_negative() {
                 v.push(Operation::FlipHorizontally);
@@ -33,6 +350,251 @@ impl Operations {
 
         Operations(v)
     }
+
+    /// The same operations, but with the ones that depend on what the image looks like (trim,
+    /// smart cropping and auto fills) worked out against this image, so that every frame of an
+    /// animation gets cut (and filled) the same way
+    pub fn pinned_to(&self, image: &DynamicImage) -> Operations {
+        let smart_focal = smart_focal_point(image, self);
+        let pinned = self
+            .0
+            .iter()
+            .map(|op| match *op {
+                // Trims only ever come first, so they can be worked out on the image as is
+                Operation::Trim { from, tolerance } => {
+                    let (trimmed, (left, top)) = trim(image.clone(), from, tolerance);
+                    Operation::Crop {
+                        left,
+                        top,
+                        right: left + trimmed.width(),
+                        bottom: top + trimmed.height(),
+                    }
+                }
+                Operation::Resize {
+                    width,
+                    height,
+                    mode,
+                    horizontal_align,
+                    vertical_align,
+                    focal: None,
+                    smart: true,
+                } => Operation::Resize {
+                    width,
+                    height,
+                    mode,
+                    horizontal_align,
+                    vertical_align,
+                    focal: smart_focal.map(|point| FocalRegion {
+                        left: point.x,
+                        top: point.y,
+                        right: point.x,
+                        bottom: point.y,
+                    }),
+                    smart: false,
+                },
+                Operation::Rotate {
+                    degrees,
+                    fill: Fill::Auto,
+                } => Operation::Rotate {
+                    degrees,
+                    fill: Fill::Colour(dominant_edge_colour(image)),
+                },
+                Operation::Pad {
+                    width,
+                    height,
+                    adaptive,
+                    horizontal_align,
+                    vertical_align,
+                    fill: Fill::Auto,
+                } => Operation::Pad {
+                    width,
+                    height,
+                    adaptive,
+                    horizontal_align,
+                    vertical_align,
+                    fill: Fill::Colour(dominant_edge_colour(image)),
+                },
+                ref other => other.clone(),
+            })
+            .collect();
+        Operations(pinned)
+    }
+
+    /// The same operations, for a source that's been rendered at a different scale than the one
+    /// its coordinates (crops and focal regions) were given in, e.g. an SVG
+    pub fn scaled(&self, factor: f32) -> Operations {
+        let scale = |v: u32| (v as f32 * factor).round() as u32;
+        let scaled = self
+            .0
+            .iter()
+            .map(|op| match *op {
+                Operation::Crop {
+                    left,
+                    top,
+                    right,
+                    bottom,
+                } => Operation::Crop {
+                    left: scale(left),
+                    top: scale(top),
+                    right: scale(right),
+                    bottom: scale(bottom),
+                },
+                Operation::Resize {
+                    width,
+                    height,
+                    mode,
+                    horizontal_align,
+                    vertical_align,
+                    focal,
+                    smart,
+                } => Operation::Resize {
+                    width,
+                    height,
+                    mode,
+                    horizontal_align,
+                    vertical_align,
+                    focal: focal.map(|region| FocalRegion {
+                        left: scale(region.left),
+                        top: scale(region.top),
+                        right: scale(region.right),
+                        bottom: scale(region.bottom),
+                    }),
+                    smart,
+                },
+                ref other => other.clone(),
+            })
+            .collect();
+        Operations(scaled)
+    }
+
+    /// Whether the source image should be turned the way its EXIF orientation says
+    pub fn autorotates(&self) -> bool {
+        !self.0.contains(&Operation::NoAutorotate)
+    }
+
+    /// Whether a resize relies on smart cropping to pick what to keep
+    pub fn has_smart_resize(&self) -> bool {
+        self.0.iter().any(|op| {
+            matches!(
+                op,
+                Operation::Resize {
+                    focal: None,
+                    smart: true,
+                    ..
+                }
+            )
+        })
+    }
+}
+
+impl TryFrom<&ImageProcessingPath> for Operations {
+    type Error = ValidationErrors;
+
+    fn try_from(path: &ImageProcessingPath) -> Result<Self, Self::Error> {
+        parse_processing_path(path).map(|(operations, _)| operations)
+    }
+}
+
+/// Turns the path into the operations to run on the image and the options to write it out with
+pub fn parse_processing_path(
+    path: &ImageProcessingPath,
+) -> Result<(Operations, OutputOptions), ValidationErrors> {
+    let mut problems = Vec::new();
+    let mut parsed = ParsedFilters {
+        resize_options: ResizeOptions {
+            mode: path.fit_in.map(ResizeMode::from).unwrap_or_default(),
+            horizontal_align: path.horizontal_align.map(Into::into).unwrap_or_default(),
+            vertical_align: path.vertical_align.map(Into::into).unwrap_or_default(),
+            focal: None,
+            smart: path.smart,
+        },
+        filters: Vec::new(),
+        output: OutputOptions::default(),
+        no_autorotate: false,
+        rotations: Vec::new(),
+        fill: None,
+    };
+    for filter in &path.filters {
+        match parse_filter(&filter.name, &filter.args, &mut parsed) {
+            Some(Ok(())) => {}
+            Some(Err(e)) => problems.push(e.to_string()),
+            None => problems.push(not_supported_yet(&format!("filters:{}", filter.name))),
+        }
+    }
+    // Only the fit-in modes can leave the result smaller than the box, so with any other resize
+    // there's nothing for the fill to pad (it still fills in what rotating uncovers)
+    let pads = matches!(
+        parsed.resize_options.mode,
+        ResizeMode::FitIn | ResizeMode::AdaptiveFitIn
+    );
+    if parsed.fill.is_some() && path.resize.is_some() && !pads && parsed.rotations.is_empty() {
+        problems.push(
+            "Filter [fill] only pads fit-in and adaptive-fit-in resizes, or fills what rotate uncovers"
+                .to_string(),
+        );
+    }
+
+    if problems.is_empty() {
+        let mut operations = Vec::new();
+        if parsed.no_autorotate {
+            operations.push(Operation::NoAutorotate);
+        }
+        if let Some(trim) = path.trim {
+            operations.push(Operation::Trim {
+                from: trim.from.into(),
+                tolerance: trim.tolerance,
+            });
+        }
+        if let Some(crop) = path.crop {
+            operations.push(Operation::Crop {
+                left: crop.left,
+                top: crop.top,
+                right: crop.right,
+                bottom: crop.bottom,
+            });
+        }
+        let resize: Option<ImageResize> = path.resize.map(Into::into);
+        let options = parsed.resize_options;
+        operations.extend(Operations::build_with_options(&resize, options).0);
+        if let (Some(resize), Some(fill), true) = (resize, parsed.fill, pads) {
+            operations.push(Operation::Pad {
+                width: resize.target_width.unsigned_abs(),
+                height: resize.target_height.unsigned_abs(),
+                adaptive: options.mode == ResizeMode::AdaptiveFitIn,
+                horizontal_align: options.horizontal_align,
+                vertical_align: options.vertical_align,
+                fill,
+            });
+        }
+        let fill = parsed.fill.unwrap_or_default();
+        let mut rotations = parsed.rotations.into_iter().peekable();
+        for (index, filter) in parsed.filters.into_iter().enumerate() {
+            while let Some((_, degrees)) = rotations.next_if(|(before, _)| *before == index) {
+                operations.extend(rotation(degrees, fill));
+            }
+            operations.push(Operation::Filter(filter));
+        }
+        operations.extend(rotations.filter_map(|(_, degrees)| rotation(degrees, fill)));
+        Ok((Operations(operations), parsed.output))
+    } else {
+        Err(ValidationErrors(problems))
+    }
+}
+
+// Right angles get turned without touching the pixels, a full turn does nothing
+fn rotation(degrees: Decimal, fill: Fill) -> Option<Operation> {
+    let degrees = Decimal::from_hundredths(degrees.hundredths().rem_euclid(36_000));
+    match degrees.hundredths() {
+        0 => None,
+        9_000 => Some(Operation::Rotate90),
+        18_000 => Some(Operation::Rotate180),
+        27_000 => Some(Operation::Rotate270),
+        _ => Some(Operation::Rotate { degrees, fill }),
+    }
+}
+
+fn not_supported_yet(what: &str) -> String {
+    format!("[{what}] is not supported yet")
 }
 
 // Runner of operations
@@ -40,6 +602,23 @@ impl Operations {
 #[allow(async_fn_in_trait)]
 pub trait OperationsRunner {
     async fn run(&self, image: DynamicImage, operations: &Operations) -> DynamicImage;
+
+    // Runs the operations on every frame of an animation, keeping the frame delays
+    async fn run_frames(&self, frames: Vec<Frame>, operations: &Operations) -> Vec<Frame> {
+        let operations = match frames.first() {
+            Some(first) => operations.pinned_to(&DynamicImage::ImageRgba8(first.buffer().clone())),
+            None => return frames,
+        };
+        let mut processed = Vec::with_capacity(frames.len());
+        for frame in frames {
+            let delay = frame.delay();
+            let image = self
+                .run(DynamicImage::ImageRgba8(frame.into_buffer()), &operations)
+                .await;
+            processed.push(Frame::from_parts(image.to_rgba8(), 0, 0, delay));
+        }
+        processed
+    }
 }
 
 #[derive(Debug)]
@@ -48,22 +627,510 @@ pub struct SingletonOperationsRunner;
 impl OperationsRunner for SingletonOperationsRunner {
     #[instrument(skip(image))]
     async fn run(&self, image: DynamicImage, operations: &Operations) -> DynamicImage {
+        // Where the current image's top-left corner sits in the source image, so that
+        // coordinates given relative to the source (e.g. focal regions) can be translated
+        let mut origin = (0, 0);
         operations.0.iter().fold(image, |next, op| match op {
-            Operation::Resize { width, height } => {
-                let resize_to_width = if *width == 0 { next.width() } else { *width };
-                let resize_to_height = if *height == 0 { next.height() } else { *height };
-                next.resize(
-                    resize_to_width,
-                    resize_to_height,
-                    image::imageops::FilterType::Lanczos3,
+            Operation::Trim { from, tolerance } => {
+                let (trimmed, (left, top)) = trim(next, *from, *tolerance);
+                origin = (origin.0 + left, origin.1 + top);
+                trimmed
+            }
+            Operation::Crop {
+                left,
+                top,
+                right,
+                bottom,
+            } => {
+                origin = (origin.0 + left, origin.1 + top);
+                next.crop_imm(
+                    *left,
+                    *top,
+                    right.saturating_sub(*left),
+                    bottom.saturating_sub(*top),
                 )
             }
+            Operation::Resize {
+                width,
+                height,
+                mode,
+                horizontal_align,
+                vertical_align,
+                focal,
+                smart,
+            } => {
+                let gravity = match focal {
+                    Some(focal) => {
+                        let (x, y) = focal.center();
+                        Gravity::Focus {
+                            x: x - origin.0 as f64,
+                            y: y - origin.1 as f64,
+                        }
+                    }
+                    None if *smart => {
+                        let point = detect_focal_point(&next);
+                        Gravity::Focus {
+                            x: point.x as f64,
+                            y: point.y as f64,
+                        }
+                    }
+                    None => Gravity::Aligned(*horizontal_align, *vertical_align),
+                };
+                resize(next, *width, *height, *mode, gravity)
+            }
             Operation::FlipHorizontally => next.fliph(),
             Operation::FlipVertically => next.flipv(),
+            Operation::Rotate90 => next.rotate270(),
+            Operation::Rotate180 => next.rotate180(),
+            Operation::Rotate270 => next.rotate90(),
+            Operation::Rotate { degrees, fill } => rotate(&next, degrees.as_f32(), *fill),
+            Operation::Pad {
+                width,
+                height,
+                adaptive,
+                horizontal_align,
+                vertical_align,
+                fill,
+            } => {
+                let (width, height) =
+                    padded_size(next.width(), next.height(), *width, *height, *adaptive);
+                pad(
+                    &next,
+                    width,
+                    height,
+                    (*horizontal_align, *vertical_align),
+                    *fill,
+                )
+            }
+            Operation::Filter(filter) => filter.apply(next),
+            Operation::NoAutorotate => next,
         })
     }
 }
 
+/// The focal point that smart cropping detects for the first smart resize in the operations
+/// (in source image pixels), or None if there's no smart resize or it has an explicit focal region
+pub fn smart_focal_point(image: &DynamicImage, operations: &Operations) -> Option<FocalPoint> {
+    let mut origin = (0, 0);
+    let mut cropped: Option<DynamicImage> = None;
+    for op in &operations.0 {
+        match op {
+            Operation::Trim { from, tolerance } => {
+                let current = cropped.take().unwrap_or_else(|| image.clone());
+                let (trimmed, (left, top)) = trim(current, *from, *tolerance);
+                origin = (origin.0 + left, origin.1 + top);
+                cropped = Some(trimmed);
+            }
+            Operation::Crop {
+                left,
+                top,
+                right,
+                bottom,
+            } => {
+                origin = (origin.0 + left, origin.1 + top);
+                let current = cropped.as_ref().unwrap_or(image);
+                cropped = Some(current.crop_imm(
+                    *left,
+                    *top,
+                    right.saturating_sub(*left),
+                    bottom.saturating_sub(*top),
+                ));
+            }
+            Operation::Resize {
+                focal: None,
+                smart: true,
+                ..
+            } => {
+                let point = detect_focal_point(cropped.as_ref().unwrap_or(image));
+                return Some(FocalPoint {
+                    x: point.x + origin.0,
+                    y: point.y + origin.1,
+                });
+            }
+            Operation::Resize { .. } => return None,
+            _ => {}
+        }
+    }
+    None
+}
+
+// Cuts away the border around the image that is (within tolerance) the colour of the reference
+// corner, returning the trimmed image and where its top-left corner was in the original.
+// Images that are entirely border are left alone.
+fn trim(image: DynamicImage, from: TrimFrom, tolerance: u16) -> (DynamicImage, (u32, u32)) {
+    let (left, top, width, height) = trim_bounds(&image, from, tolerance);
+    if (width, height) == (image.width(), image.height()) {
+        return (image, (0, 0));
+    }
+    (image.crop_imm(left, top, width, height), (left, top))
+}
+
+/// What trimming keeps of the image, as left, top, width and height (all of it if it's all border)
+pub fn trim_bounds(image: &DynamicImage, from: TrimFrom, tolerance: u16) -> (u32, u32, u32, u32) {
+    let rgba = image.to_rgba8();
+    let (width, height) = rgba.dimensions();
+    if width == 0 || height == 0 {
+        return (0, 0, width, height);
+    }
+    let reference = match from {
+        TrimFrom::TopLeft => *rgba.get_pixel(0, 0),
+        TrimFrom::BottomRight => *rgba.get_pixel(width - 1, height - 1),
+    };
+    let max_distance_squared = tolerance as u32 * tolerance as u32;
+    let is_border = |x: u32, y: u32| {
+        let pixel = rgba.get_pixel(x, y);
+        let distance_squared: u32 = pixel
+            .0
+            .iter()
+            .zip(reference.0.iter())
+            .map(|(a, b)| (*a as i32 - *b as i32).pow(2) as u32)
+            .sum();
+        distance_squared <= max_distance_squared
+    };
+
+    let (mut left, mut top, mut right, mut bottom) = (width, height, 0, 0);
+    for y in 0..height {
+        for x in 0..width {
+            if !is_border(x, y) {
+                left = left.min(x);
+                top = top.min(y);
+                right = right.max(x + 1);
+                bottom = bottom.max(y + 1);
+            }
+        }
+    }
+    if right <= left || bottom <= top {
+        return (0, 0, width, height);
+    }
+    (left, top, right - left, bottom - top)
+}
+
+const RESIZE_FILTER: FilterType = FilterType::Lanczos3;
+
+// Decides what to keep when a cover resize has to cut part of the image off
+#[derive(Debug, Clone, Copy)]
+enum Gravity {
+    Aligned(HorizontalAlign, VerticalAlign),
+    // A point (in pixels of the image being resized) to keep as close to the center as possible
+    Focus { x: f64, y: f64 },
+}
+
+// A 0 width or height means "whatever keeps the aspect ratio", and 0x0 leaves the image alone
+fn resize(
+    image: DynamicImage,
+    width: u32,
+    height: u32,
+    mode: ResizeMode,
+    gravity: Gravity,
+) -> DynamicImage {
+    let (source_width, source_height) = (image.width(), image.height());
+    let (resized_width, resized_height) =
+        resized_size(source_width, source_height, width, height, mode);
+    if (resized_width, resized_height) == (source_width, source_height) {
+        return image;
+    }
+    if mode != ResizeMode::Cover || width == 0 || height == 0 {
+        return image.resize_exact(resized_width, resized_height, RESIZE_FILTER);
+    }
+
+    // Cut the source down to the aspect ratio of the box first, so that only what's kept gets
+    // resized (scaling all of a very wide or tall image up to cover the box could be huge)
+    let ratio = (width as f64 / source_width as f64).max(height as f64 / source_height as f64);
+    let kept =
+        |side: u32, source_side: u32| ((side as f64 / ratio).round() as u32).clamp(1, source_side);
+    let (kept_width, kept_height) = (kept(width, source_width), kept(height, source_height));
+    let overflow_x = source_width - kept_width;
+    let overflow_y = source_height - kept_height;
+    let (x, y) = match gravity {
+        Gravity::Aligned(horizontal_align, vertical_align) => {
+            let x = match horizontal_align {
+                HorizontalAlign::Left => 0,
+                HorizontalAlign::Center => overflow_x / 2,
+                HorizontalAlign::Right => overflow_x,
+            };
+            let y = match vertical_align {
+                VerticalAlign::Top => 0,
+                VerticalAlign::Middle => overflow_y / 2,
+                VerticalAlign::Bottom => overflow_y,
+            };
+            (x, y)
+        }
+        Gravity::Focus { x, y } => {
+            let centered = |focus: f64, kept: u32, overflow: u32| {
+                (focus - kept as f64 / 2.0)
+                    .round()
+                    .clamp(0.0, overflow as f64) as u32
+            };
+            (
+                centered(x, kept_width, overflow_x),
+                centered(y, kept_height, overflow_y),
+            )
+        }
+    };
+    image
+        .crop_imm(x, y, kept_width, kept_height)
+        .resize_exact(width, height, RESIZE_FILTER)
+}
+
+/// The size an image of the source size comes out at when resized to the box with the mode
+pub fn resized_size(
+    source_width: u32,
+    source_height: u32,
+    width: u32,
+    height: u32,
+    mode: ResizeMode,
+) -> (u32, u32) {
+    if width == 0 && height == 0 {
+        return (source_width, source_height);
+    }
+    let (width, height) = match mode {
+        ResizeMode::AdaptiveFitIn | ResizeMode::AdaptiveFullFitIn => {
+            flipped_to_match(width, height, source_width, source_height)
+        }
+        _ => (width, height),
+    };
+    let width_ratio = width as f64 / source_width as f64;
+    let height_ratio = height as f64 / source_height as f64;
+    // Like Thumbor, the fit-in family never enlarges
+    let max_ratio = match mode {
+        ResizeMode::Cover | ResizeMode::Stretch => f64::INFINITY,
+        _ => 1.0,
+    };
+    let ratio = if width == 0 || height == 0 {
+        width_ratio.max(height_ratio)
+    } else {
+        match mode {
+            ResizeMode::Cover | ResizeMode::Stretch => return (width, height),
+            ResizeMode::FitIn | ResizeMode::AdaptiveFitIn => width_ratio.min(height_ratio),
+            ResizeMode::FullFitIn | ResizeMode::AdaptiveFullFitIn => width_ratio.max(height_ratio),
+        }
+    };
+    scale(source_width, source_height, ratio.min(max_ratio))
+}
+
+// Counter-clockwise, sampling each pixel of the (bigger) result from the source bilinearly, with
+// the backdrop standing in for whatever lies outside of it so that the edges come out smooth
+/// The size of the canvas an image of the size gets turned by degrees (counter-clockwise) on,
+/// which grows to fit the corners
+pub fn rotated_size(width: u32, height: u32, degrees: f32) -> (u32, u32) {
+    let (width, height) = (width as f32, height as f32);
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    (
+        (width * cos.abs() + height * sin.abs()).round().max(1.0) as u32,
+        (width * sin.abs() + height * cos.abs()).round().max(1.0) as u32,
+    )
+}
+
+/// The size an image of the size gets padded out to for the box (a side left at 0 stays as is)
+pub fn padded_size(
+    source_width: u32,
+    source_height: u32,
+    width: u32,
+    height: u32,
+    adaptive: bool,
+) -> (u32, u32) {
+    let (width, height) = if adaptive {
+        flipped_to_match(width, height, source_width, source_height)
+    } else {
+        (width, height)
+    };
+    let side = |side: u32, current: u32| {
+        if side == 0 {
+            current
+        } else {
+            side.max(current)
+        }
+    };
+    (side(width, source_width), side(height, source_height))
+}
+
+fn rotate(image: &DynamicImage, degrees: f32, fill: Fill) -> DynamicImage {
+    let source = image.to_rgba32f();
+    let (width, height) = (source.width() as f32, source.height() as f32);
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    let (rotated_width, rotated_height) = rotated_size(source.width(), source.height(), degrees);
+    let (rotated_width, rotated_height) = (rotated_width as f32, rotated_height as f32);
+
+    // Colours are weighted by alpha while blending, so transparency doesn't darken anything
+    let premultiply = |[r, g, b, a]: [f32; 4]| [r * a, g * a, b * a, a];
+    let backdrop = backdrop(image, fill, rotated_width as u32, rotated_height as u32);
+    let pixel_at = |x: i64, y: i64, background: [f32; 4]| {
+        if x < 0 || y < 0 || x >= source.width() as i64 || y >= source.height() as i64 {
+            premultiply(background)
+        } else {
+            premultiply(source.get_pixel(x as u32, y as u32).0)
+        }
+    };
+
+    let rotated = Rgba32FImage::from_fn(rotated_width as u32, rotated_height as u32, |x, y| {
+        // Relative to the centres, y pointing down
+        let (dx, dy) = (
+            x as f32 + 0.5 - rotated_width / 2.0,
+            y as f32 + 0.5 - rotated_height / 2.0,
+        );
+        let source_x = dx * cos - dy * sin + width / 2.0 - 0.5;
+        let source_y = dx * sin + dy * cos + height / 2.0 - 0.5;
+        let (left, top) = (source_x.floor(), source_y.floor());
+        let (right_weight, bottom_weight) = (source_x - left, source_y - top);
+        let mut sum = [0.0; 4];
+        for (offset_x, offset_y, weight) in [
+            (0, 0, (1.0 - right_weight) * (1.0 - bottom_weight)),
+            (1, 0, right_weight * (1.0 - bottom_weight)),
+            (0, 1, (1.0 - right_weight) * bottom_weight),
+            (1, 1, right_weight * bottom_weight),
+        ] {
+            let background = backdrop.get_pixel(x, y).0;
+            let pixel = pixel_at(left as i64 + offset_x, top as i64 + offset_y, background);
+            for (total, value) in sum.iter_mut().zip(pixel) {
+                *total += weight * value;
+            }
+        }
+        let [r, g, b, a] = sum;
+        if a > 0.0 {
+            image::Rgba([r / a, g / a, b / a, a].map(|c| c.clamp(0.0, 1.0)))
+        } else {
+            image::Rgba([0.0; 4])
+        }
+    });
+
+    from_rgba32f(rotated, filled_colour_type(image.color(), fill))
+}
+
+// Puts the image on a canvas of the given size (at least as big as the image), aligned, with the
+// fill around it
+fn pad(
+    image: &DynamicImage,
+    width: u32,
+    height: u32,
+    (horizontal_align, vertical_align): (HorizontalAlign, VerticalAlign),
+    fill: Fill,
+) -> DynamicImage {
+    if (width, height) == (image.width(), image.height()) {
+        return image.clone();
+    }
+    let (spare_width, spare_height) = (width - image.width(), height - image.height());
+    let left = match horizontal_align {
+        HorizontalAlign::Left => 0,
+        HorizontalAlign::Center => spare_width / 2,
+        HorizontalAlign::Right => spare_width,
+    };
+    let top = match vertical_align {
+        VerticalAlign::Top => 0,
+        VerticalAlign::Middle => spare_height / 2,
+        VerticalAlign::Bottom => spare_height,
+    };
+    let mut canvas = backdrop(image, fill, width, height);
+    imageops::replace(&mut canvas, &image.to_rgba32f(), left as i64, top as i64);
+    from_rgba32f(canvas, filled_colour_type(image.color(), fill))
+}
+
+// What goes behind the image where padding or rotating leaves the canvas uncovered
+fn backdrop(image: &DynamicImage, fill: Fill, width: u32, height: u32) -> Rgba32FImage {
+    match fill {
+        Fill::Colour(colour) => Rgba32FImage::from_pixel(
+            width,
+            height,
+            image::Rgba(colour.0.map(|channel| channel as f32 / 255.0)),
+        ),
+        Fill::Transparent => Rgba32FImage::new(width, height),
+        Fill::Auto => backdrop(
+            image,
+            Fill::Colour(dominant_edge_colour(image)),
+            width,
+            height,
+        ),
+        Fill::Blur => {
+            // Blurring a small copy and scaling it up is much cheaper than blurring at full size,
+            // and comes out just as soft
+            let small = image.resize_to_fill(
+                (width / 16).max(1),
+                (height / 16).max(1),
+                FilterType::Triangle,
+            );
+            let blurred = gaussian_blur(&small.to_rgba32f(), 2, 2.0);
+            imageops::resize(&blurred, width, height, FilterType::Triangle)
+        }
+    }
+}
+
+// Alpha is only needed if the image or the fill has some
+fn filled_colour_type(colour_type: ColorType, fill: Fill) -> ColorType {
+    let opaque_fill = match fill {
+        Fill::Colour(colour) => colour.0[3] == u8::MAX,
+        Fill::Transparent => false,
+        // Made out of the image, so as opaque as it is
+        Fill::Blur | Fill::Auto => true,
+    };
+    let deep = colour_type.bytes_per_pixel() / colour_type.channel_count() > 1;
+    match (colour_type.has_alpha() || !opaque_fill, deep) {
+        (false, _) => colour_type,
+        (true, true) => ColorType::Rgba16,
+        (true, false) => ColorType::Rgba8,
+    }
+}
+
+/// The most common colour along the edges of the image, which is usually its background. Colours
+/// that are close count as the same, and get averaged.
+pub fn dominant_edge_colour(image: &DynamicImage) -> Colour {
+    let pixels = image.to_rgba8();
+    let (width, height) = pixels.dimensions();
+    let edges = (0..width)
+        .flat_map(|x| [(x, 0), (x, height - 1)])
+        .chain((0..height).flat_map(|y| [(0, y), (width - 1, y)]));
+    let mut buckets: HashMap<[u8; 4], (u32, [u32; 4])> = HashMap::new();
+    for (x, y) in edges {
+        let pixel = pixels.get_pixel(x, y).0;
+        let (count, sums) = buckets.entry(pixel.map(|c| c >> 4)).or_default();
+        *count += 1;
+        for (sum, channel) in sums.iter_mut().zip(pixel) {
+            *sum += channel as u32;
+        }
+    }
+    // Ties are broken by the bucket, so the answer doesn't depend on the order of the map
+    let (_, (count, sums)) = buckets
+        .into_iter()
+        .max_by_key(|(bucket, (count, _))| (*count, *bucket))
+        .unwrap_or_default();
+    Colour(sums.map(|sum| ((sum + count / 2) / count.max(1)) as u8))
+}
+
+// The adaptive modes flip the box when that better matches the orientation of the image
+fn flipped_to_match(width: u32, height: u32, image_width: u32, image_height: u32) -> (u32, u32) {
+    if width != 0 && height != 0 && (width > height) != (image_width > image_height) {
+        (height, width)
+    } else {
+        (width, height)
+    }
+}
+
+/// Shrinks the image by the factor, e.g. when it won't otherwise fit in filters:max_bytes(n)
+pub fn scale_down(image: &DynamicImage, factor: f32) -> DynamicImage {
+    let (width, height) = scale(image.width(), image.height(), factor as f64);
+    image.resize_exact(width, height, RESIZE_FILTER)
+}
+
+/// The same for every frame of an animation
+pub fn scale_down_frames(frames: &[Frame], factor: f32) -> Vec<Frame> {
+    frames
+        .iter()
+        .map(|frame| {
+            let buffer = frame.buffer();
+            let (width, height) = scale(buffer.width(), buffer.height(), factor as f64);
+            let (left, top) = (
+                (frame.left() as f32 * factor) as u32,
+                (frame.top() as f32 * factor) as u32,
+            );
+            let resized = image::imageops::resize(buffer, width, height, RESIZE_FILTER);
+            Frame::from_parts(resized, left, top, frame.delay())
+        })
+        .collect()
+}
+
+fn scale(width: u32, height: u32, ratio: f64) -> (u32, u32) {
+    let scale_side = |side: u32| ((side as f64 * ratio).round() as u32).max(1);
+    (scale_side(width), scale_side(height))
+}
+
 #[cfg(test)]
 mod tests {
     use std::io::Cursor;
@@ -87,7 +1154,12 @@ mod tests {
         assert_eq!(
             Operation::Resize {
                 width: 1,
-                height: 2
+                height: 2,
+                mode: ResizeMode::Cover,
+                horizontal_align: HorizontalAlign::Center,
+                vertical_align: VerticalAlign::Middle,
+                focal: None,
+                smart: false,
             },
             r.0[0]
         );
@@ -102,7 +1174,12 @@ mod tests {
         assert_eq!(
             Operation::Resize {
                 width: 3,
-                height: 4
+                height: 4,
+                mode: ResizeMode::Cover,
+                horizontal_align: HorizontalAlign::Center,
+                vertical_align: VerticalAlign::Middle,
+                focal: None,
+                smart: false,
             },
             r.0[0]
         );
@@ -110,6 +1187,57 @@ mod tests {
         assert_eq!(Operation::FlipVertically, r.0[2]);
     }
 
+    #[test]
+    fn test_operations_try_from_path() -> anyhow::Result<()> {
+        let path: ImageProcessingPath = "-3x4/https://beachape.com/images/lol.png".parse()?;
+        let r = Operations::try_from(&path).map_err(|e| anyhow::anyhow!("{:?}", e.0))?;
+        assert_eq!(
+            Operations::build(&Some(ImageResize {
+                target_width: -3,
+                target_height: 4,
+            })),
+            r
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_operations_try_from_path_without_resize() -> anyhow::Result<()> {
+        let path: ImageProcessingPath = "https://beachape.com/images/lol.png".parse()?;
+        let r = Operations::try_from(&path).map_err(|e| anyhow::anyhow!("{:?}", e.0))?;
+        assert!(r.0.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_operations_try_from_path_with_crop() -> anyhow::Result<()> {
+        let path: ImageProcessingPath =
+            "10x20:110x220/-3x4/https://beachape.com/images/lol.png".parse()?;
+        let r = Operations::try_from(&path).map_err(|e| anyhow::anyhow!("{:?}", e.0))?;
+        assert_eq!(
+            vec![
+                Operation::Crop {
+                    left: 10,
+                    top: 20,
+                    right: 110,
+                    bottom: 220
+                },
+                Operation::Resize {
+                    width: 3,
+                    height: 4,
+                    mode: ResizeMode::Cover,
+                    horizontal_align: HorizontalAlign::Center,
+                    vertical_align: VerticalAlign::Middle,
+                    focal: None,
+                    smart: false,
+                },
+                Operation::FlipHorizontally
+            ],
+            r.0
+        );
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_operations_runner() {
         let image_bin = include_bytes!("not-aliens.jpg");
@@ -125,7 +1253,7 @@ mod tests {
 
         let result = SingletonOperationsRunner.run(image, &operations).await;
         assert_eq!(3, result.width());
-        assert_eq!(2, result.height());
+        assert_eq!(4, result.height());
     }
 
     #[tokio::test]
@@ -147,4 +1275,749 @@ mod tests {
         assert_eq!(original_image.width(), result.width());
         assert_eq!(original_image.height(), result.height());
     }
+
+    #[tokio::test]
+    async fn test_operations_runner_crop() {
+        let image_bin = include_bytes!("not-aliens.jpg");
+        let image_reader = ImageReader::new(Cursor::new(image_bin))
+            .with_guessed_format()
+            .unwrap();
+
+        let image = image_reader.decode().unwrap();
+        let operations = Operations(vec![Operation::Crop {
+            left: 10,
+            top: 20,
+            right: 60,
+            bottom: 50,
+        }]);
+
+        let result = SingletonOperationsRunner.run(image, &operations).await;
+        assert_eq!(50, result.width());
+        assert_eq!(30, result.height());
+    }
+
+    #[test]
+    fn test_operations_try_from_path_with_resize_modes() -> anyhow::Result<()> {
+        let cases = [
+            ("100x200", ResizeMode::Cover),
+            ("fit-in/100x200", ResizeMode::FitIn),
+            ("full-fit-in/100x200", ResizeMode::FullFitIn),
+            ("adaptive-fit-in/100x200", ResizeMode::AdaptiveFitIn),
+            (
+                "adaptive-full-fit-in/100x200",
+                ResizeMode::AdaptiveFullFitIn,
+            ),
+            ("100x200/filters:stretch()", ResizeMode::Stretch),
+        ];
+        for (path_prefix, expected_mode) in cases {
+            let path: ImageProcessingPath =
+                format!("{path_prefix}/https://beachape.com/images/lol.png").parse()?;
+            let r = Operations::try_from(&path).map_err(|e| anyhow::anyhow!("{:?}", e.0))?;
+            assert_eq!(
+                vec![Operation::Resize {
+                    width: 100,
+                    height: 200,
+                    mode: expected_mode,
+                    horizontal_align: HorizontalAlign::Center,
+                    vertical_align: VerticalAlign::Middle,
+                    focal: None,
+                    smart: false,
+                }],
+                r.0,
+                "{path_prefix}"
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_resize_modes() {
+        // Landscape, 2:1
+        let image = DynamicImage::new(400, 200, image::ColorType::Rgb8);
+        let cases = [
+            (100, 100, ResizeMode::Cover, (100, 100)),
+            (100, 0, ResizeMode::Cover, (100, 50)),
+            (0, 100, ResizeMode::Cover, (200, 100)),
+            (800, 800, ResizeMode::Cover, (800, 800)),
+            (100, 100, ResizeMode::FitIn, (100, 50)),
+            (800, 800, ResizeMode::FitIn, (400, 200)),
+            (100, 100, ResizeMode::FullFitIn, (200, 100)),
+            // Only one side fits, but the fit-in family still never enlarges
+            (300, 300, ResizeMode::FullFitIn, (400, 200)),
+            (300, 500, ResizeMode::AdaptiveFullFitIn, (400, 200)),
+            (0, 300, ResizeMode::FitIn, (400, 200)),
+            (100, 200, ResizeMode::AdaptiveFitIn, (200, 100)),
+            (100, 200, ResizeMode::FitIn, (100, 50)),
+            (50, 200, ResizeMode::AdaptiveFullFitIn, (200, 100)),
+            (100, 100, ResizeMode::Stretch, (100, 100)),
+            (0, 0, ResizeMode::Stretch, (400, 200)),
+        ];
+        for (width, height, mode, (expected_width, expected_height)) in cases {
+            let result = resize(
+                image.clone(),
+                width,
+                height,
+                mode,
+                Gravity::Aligned(HorizontalAlign::default(), VerticalAlign::default()),
+            );
+            assert_eq!(
+                (expected_width, expected_height),
+                (result.width(), result.height()),
+                "{width}x{height} {mode:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_operations_try_from_path_with_alignment() -> anyhow::Result<()> {
+        let path: ImageProcessingPath =
+            "100x200/left/top/https://beachape.com/images/lol.png".parse()?;
+        let r = Operations::try_from(&path).map_err(|e| anyhow::anyhow!("{:?}", e.0))?;
+        assert_eq!(
+            vec![Operation::Resize {
+                width: 100,
+                height: 200,
+                mode: ResizeMode::Cover,
+                horizontal_align: HorizontalAlign::Left,
+                vertical_align: VerticalAlign::Top,
+                focal: None,
+                smart: false,
+            }],
+            r.0
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_resize_cover_alignment() {
+        // The red channel marks the right half and the blue channel the bottom half
+        let image = DynamicImage::ImageRgb8(image::RgbImage::from_fn(40, 40, |x, y| {
+            let r = if x < 20 { 0 } else { 255 };
+            let b = if y < 20 { 0 } else { 255 };
+            image::Rgb([r, 0, b])
+        }));
+        // Landscape target on a square image, so we cut from top and bottom
+        let top = resize(
+            image.clone(),
+            40,
+            10,
+            ResizeMode::Cover,
+            Gravity::Aligned(HorizontalAlign::Center, VerticalAlign::Top),
+        );
+        assert_eq!(0, top.to_rgb8().get_pixel(0, 5)[2]);
+        let bottom = resize(
+            image.clone(),
+            40,
+            10,
+            ResizeMode::Cover,
+            Gravity::Aligned(HorizontalAlign::Center, VerticalAlign::Bottom),
+        );
+        assert_eq!(255, bottom.to_rgb8().get_pixel(0, 5)[2]);
+
+        // Portrait target on a square image, so we cut from the sides
+        let left = resize(
+            image.clone(),
+            10,
+            40,
+            ResizeMode::Cover,
+            Gravity::Aligned(HorizontalAlign::Left, VerticalAlign::Middle),
+        );
+        assert_eq!(0, left.to_rgb8().get_pixel(5, 0)[0]);
+        let right = resize(
+            image,
+            10,
+            40,
+            ResizeMode::Cover,
+            Gravity::Aligned(HorizontalAlign::Right, VerticalAlign::Middle),
+        );
+        assert_eq!(255, right.to_rgb8().get_pixel(5, 0)[0]);
+    }
+
+    #[test]
+    fn test_operations_try_from_path_with_focal() -> anyhow::Result<()> {
+        let path: ImageProcessingPath =
+            "100x200/filters:focal(10x20:30x40)/https://beachape.com/images/lol.png".parse()?;
+        let r = Operations::try_from(&path).map_err(|e| anyhow::anyhow!("{:?}", e.0))?;
+        assert_eq!(
+            vec![Operation::Resize {
+                width: 100,
+                height: 200,
+                mode: ResizeMode::Cover,
+                horizontal_align: HorizontalAlign::Center,
+                vertical_align: VerticalAlign::Middle,
+                focal: Some(FocalRegion {
+                    left: 10,
+                    top: 20,
+                    right: 30,
+                    bottom: 40
+                }),
+                smart: false,
+            }],
+            r.0
+        );
+
+        let bad_path: ImageProcessingPath =
+            "100x200/filters:focal(10x20)/https://beachape.com/images/lol.png".parse()?;
+        let errors = Operations::try_from(&bad_path).err().unwrap();
+        assert_eq!(1, errors.0.len());
+        assert!(errors.0[0].starts_with("Invalid focal region"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_operations_runner_focal() {
+        // White 10x10 square at the right edge of a black landscape image
+        let image = DynamicImage::ImageRgb8(image::RgbImage::from_fn(100, 50, |x, y| {
+            if x >= 90 && (20..30).contains(&y) {
+                image::Rgb([255, 255, 255])
+            } else {
+                image::Rgb([0, 0, 0])
+            }
+        }));
+        let focal = FocalRegion {
+            left: 90,
+            top: 20,
+            right: 100,
+            bottom: 30,
+        };
+        let operations = Operations::build_with_options(
+            &Some(ImageResize {
+                target_width: 50,
+                target_height: 50,
+            }),
+            ResizeOptions {
+                focal: Some(focal),
+                ..Default::default()
+            },
+        );
+        let result = SingletonOperationsRunner
+            .run(image.clone(), &operations)
+            .await;
+        assert_eq!((50, 50), (result.width(), result.height()));
+        // Without the focal region, the square would have been cut off with the right side
+        assert_eq!(255, result.to_rgb8().get_pixel(45, 25)[0]);
+
+        // Focal regions are relative to the source, so they follow along after a crop
+        let mut operations_with_crop = vec![Operation::Crop {
+            left: 40,
+            top: 0,
+            right: 100,
+            bottom: 50,
+        }];
+        operations_with_crop.extend(operations.0);
+        let result = SingletonOperationsRunner
+            .run(image, &Operations(operations_with_crop))
+            .await;
+        assert_eq!(255, result.to_rgb8().get_pixel(45, 25)[0]);
+    }
+
+    #[tokio::test]
+    async fn test_operations_runner_smart() -> anyhow::Result<()> {
+        let path: ImageProcessingPath =
+            "50x50/smart/https://beachape.com/images/lol.png".parse()?;
+        let operations = Operations::try_from(&path).map_err(|e| anyhow::anyhow!("{:?}", e.0))?;
+        assert!(operations.has_smart_resize());
+
+        // Checkered patch at the right edge of a flat landscape image
+        let image = DynamicImage::ImageRgb8(image::RgbImage::from_fn(100, 50, |x, y| {
+            if x >= 85 && (15..35).contains(&y) && (x / 2 + y / 2) % 2 == 0 {
+                image::Rgb([255, 255, 255])
+            } else {
+                image::Rgb([0, 0, 0])
+            }
+        }));
+        let point = smart_focal_point(&image, &operations).unwrap();
+        assert!(point.x >= 85, "{point:?}");
+
+        let result = SingletonOperationsRunner
+            .run(image.clone(), &operations)
+            .await;
+        assert_eq!((50, 50), (result.width(), result.height()));
+        // Centered, the patch would have been cut off with the right side
+        let patch_pixels = result.to_rgb8().pixels().filter(|p| p[0] == 255).count();
+        assert!(patch_pixels > 0);
+
+        // The detected point is reported in source pixels, even after a crop
+        let mut operations_with_crop = vec![Operation::Crop {
+            left: 40,
+            top: 0,
+            right: 100,
+            bottom: 50,
+        }];
+        operations_with_crop.extend(operations.0);
+        let cropped_point = smart_focal_point(&image, &Operations(operations_with_crop)).unwrap();
+        assert!(cropped_point.x >= 85, "{cropped_point:?}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_smart_focal_point_yields_to_explicit_focal() -> anyhow::Result<()> {
+        let path: ImageProcessingPath =
+            "50x50/smart/filters:focal(0x0:10x10)/https://beachape.com/images/lol.png".parse()?;
+        let operations = Operations::try_from(&path).map_err(|e| anyhow::anyhow!("{:?}", e.0))?;
+        assert!(!operations.has_smart_resize());
+        let image = DynamicImage::new_rgb8(100, 50);
+        assert_eq!(None, smart_focal_point(&image, &operations));
+        Ok(())
+    }
+
+    #[test]
+    fn test_operations_try_from_path_with_trim() -> anyhow::Result<()> {
+        let path: ImageProcessingPath =
+            "trim:bottom-right:20/10x10:50x50/https://beachape.com/images/lol.png".parse()?;
+        let r = Operations::try_from(&path).map_err(|e| anyhow::anyhow!("{:?}", e.0))?;
+        assert_eq!(
+            vec![
+                Operation::Trim {
+                    from: TrimFrom::BottomRight,
+                    tolerance: 20,
+                },
+                Operation::Crop {
+                    left: 10,
+                    top: 10,
+                    right: 50,
+                    bottom: 50,
+                },
+            ],
+            r.0
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_trim() {
+        // Red 20x10 block on an off-white 100x50 canvas, with a black pixel in the bottom-right corner
+        let image = DynamicImage::ImageRgb8(image::RgbImage::from_fn(100, 50, |x, y| {
+            if (30..50).contains(&x) && (10..20).contains(&y) {
+                image::Rgb([255, 0, 0])
+            } else if (x, y) == (99, 49) {
+                image::Rgb([0, 0, 0])
+            } else if (x + y) % 2 == 0 {
+                image::Rgb([250, 250, 250])
+            } else {
+                image::Rgb([255, 255, 255])
+            }
+        }));
+
+        let (trimmed, origin) = trim(image.clone(), TrimFrom::TopLeft, 10);
+        assert_eq!((30, 10), origin);
+        // The black corner pixel isn't border, so it's kept
+        assert_eq!((70, 40), (trimmed.width(), trimmed.height()));
+
+        // Without tolerance, the off-white noise counts as content
+        let (untrimmed, origin) = trim(image.clone(), TrimFrom::TopLeft, 0);
+        assert_eq!((0, 0), origin);
+        assert_eq!((100, 50), (untrimmed.width(), untrimmed.height()));
+
+        // Everything but the black corner is "content" from that corner's point of view
+        let (trimmed, origin) = trim(image, TrimFrom::BottomRight, 10);
+        assert_eq!((0, 0), origin);
+        assert_eq!((100, 50), (trimmed.width(), trimmed.height()));
+
+        // A uniform image is left as is
+        let blank = DynamicImage::new_rgb8(10, 10);
+        let (trimmed, _) = trim(blank, TrimFrom::TopLeft, 0);
+        assert_eq!((10, 10), (trimmed.width(), trimmed.height()));
+    }
+
+    #[tokio::test]
+    async fn test_operations_runner_trim_before_crop() {
+        let image = DynamicImage::ImageRgb8(image::RgbImage::from_fn(100, 50, |x, y| {
+            if (30..50).contains(&x) && (10..20).contains(&y) {
+                image::Rgb([255, 0, 0])
+            } else {
+                image::Rgb([255, 255, 255])
+            }
+        }));
+        let operations = Operations(vec![
+            Operation::Trim {
+                from: TrimFrom::TopLeft,
+                tolerance: 0,
+            },
+            // Relative to the trimmed image
+            Operation::Crop {
+                left: 0,
+                top: 0,
+                right: 10,
+                bottom: 5,
+            },
+        ]);
+        let result = SingletonOperationsRunner.run(image, &operations).await;
+        assert_eq!((10, 5), (result.width(), result.height()));
+        assert!(result
+            .to_rgb8()
+            .pixels()
+            .all(|p| *p == image::Rgb([255, 0, 0])));
+    }
+
+    #[test]
+    fn test_operations_try_from_path_with_filters() -> anyhow::Result<()> {
+        let path: ImageProcessingPath =
+            "-10x20/filters:grayscale():stretch()/https://beachape.com/images/lol.png".parse()?;
+        let r = Operations::try_from(&path).map_err(|e| anyhow::anyhow!("{:?}", e.0))?;
+        assert_eq!(
+            vec![
+                Operation::Resize {
+                    width: 10,
+                    height: 20,
+                    mode: ResizeMode::Stretch,
+                    horizontal_align: HorizontalAlign::Center,
+                    vertical_align: VerticalAlign::Middle,
+                    focal: None,
+                    smart: false,
+                },
+                Operation::FlipHorizontally,
+                Operation::Filter(Filter::Grayscale),
+            ],
+            r.0
+        );
+
+        let bad_path: ImageProcessingPath =
+            "filters:grayscale(1):lol()/https://beachape.com/images/lol.png".parse()?;
+        let errors = Operations::try_from(&bad_path).err().unwrap();
+        assert_eq!(
+            vec![
+                "Filter [grayscale] takes [0] argument(s), but got [1]".to_string(),
+                "[filters:lol] is not supported yet".to_string()
+            ],
+            errors.0
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_operations_try_from_path_with_rotation() -> anyhow::Result<()> {
+        let operations = |filters: &str| -> anyhow::Result<Vec<Operation>> {
+            let path: ImageProcessingPath =
+                format!("filters:{filters}/https://beachape.com/images/lol.png").parse()?;
+            let r = Operations::try_from(&path).map_err(|e| anyhow::anyhow!("{:?}", e.0))?;
+            Ok(r.0)
+        };
+        assert_eq!(vec![Operation::Rotate90], operations("rotate(90)")?);
+        assert_eq!(vec![Operation::Rotate180], operations("rotate(540)")?);
+        assert_eq!(vec![Operation::Rotate270], operations("rotate(-90)")?);
+        assert!(operations("rotate(360)")?.is_empty());
+        assert_eq!(
+            vec![Operation::Rotate {
+                degrees: Decimal::from_hundredths(33_050),
+                fill: Fill::Colour(Colour([255, 0, 0, 255]))
+            }],
+            operations("fill(ff0000):rotate(-29.5)")?
+        );
+        // In the order they are given, along with the other filters
+        assert_eq!(
+            vec![
+                Operation::Filter(Filter::Grayscale),
+                Operation::Rotate90,
+                Operation::Filter(Filter::Blur {
+                    radius: 2,
+                    sigma: None
+                }),
+                Operation::Rotate180,
+            ],
+            operations("grayscale():rotate(90):blur(2):rotate(180)")?
+        );
+        assert!(operations("rotate(lol)").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_operations_try_from_path_with_fill() -> anyhow::Result<()> {
+        let operations = |path: &str| -> anyhow::Result<Vec<Operation>> {
+            let path: ImageProcessingPath =
+                format!("{path}/https://beachape.com/images/lol.png").parse()?;
+            let r = Operations::try_from(&path).map_err(|e| anyhow::anyhow!("{:?}", e.0))?;
+            Ok(r.0)
+        };
+        let pad = |adaptive, fill| Operation::Pad {
+            width: 300,
+            height: 200,
+            adaptive,
+            horizontal_align: HorizontalAlign::Left,
+            vertical_align: VerticalAlign::Middle,
+            fill,
+        };
+        assert_eq!(
+            Some(&pad(false, Fill::Auto)),
+            operations("fit-in/300x200/left/filters:fill(auto)")?.last()
+        );
+        assert_eq!(
+            Some(&pad(true, Fill::Colour(Colour([0, 0, 0, 0])))),
+            operations("adaptive-fit-in/300x200/left/filters:fill(00000000)")?.last()
+        );
+        // Nothing to pad when the result covers the box, which is an error unless there's a
+        // rotation to fill in instead, or when there is no box
+        for path in [
+            "300x200/filters:fill(blur)",
+            "full-fit-in/300x200/filters:fill(blur)",
+            "adaptive-full-fit-in/300x200/filters:fill(blur)",
+            "300x200/filters:stretch():fill(blur)",
+        ] {
+            assert_eq!(
+                "[\"Filter [fill] only pads fit-in and adaptive-fit-in resizes, or fills what rotate uncovers\"]",
+                operations(path).unwrap_err().to_string(),
+                "{path}"
+            );
+        }
+        assert!(!operations("300x200/filters:fill(blur):rotate(10)")?
+            .iter()
+            .any(|op| matches!(op, Operation::Pad { .. })));
+        assert!(operations("filters:fill(transparent)")?.is_empty());
+        assert_eq!(
+            vec![Operation::Rotate {
+                degrees: Decimal::from_hundredths(1_000),
+                fill: Fill::Blur
+            }],
+            operations("filters:rotate(10):fill(blur)")?
+        );
+
+        let bad_path: ImageProcessingPath =
+            "filters:fill(lol)/https://beachape.com/images/lol.png".parse()?;
+        let errors = Operations::try_from(&bad_path).err().unwrap();
+        assert_eq!(
+            vec![
+                "Invalid fill [lol], expected a hex colour like ff0000, transparent, blur or auto"
+            ],
+            errors.0
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_pad() {
+        let red =
+            DynamicImage::ImageRgb8(image::RgbImage::from_pixel(4, 2, image::Rgb([255, 0, 0])));
+        let pad = |width, height, adaptive, horizontal_align, fill| {
+            let operations = Operations(vec![Operation::Pad {
+                width,
+                height,
+                adaptive,
+                horizontal_align,
+                vertical_align: VerticalAlign::Middle,
+                fill,
+            }]);
+            let image = red.clone();
+            async move { SingletonOperationsRunner.run(image, &operations).await }
+        };
+        let white = Fill::Colour(Colour([255, 255, 255, 255]));
+
+        let padded = pad(8, 4, false, HorizontalAlign::Center, white).await;
+        let DynamicImage::ImageRgb8(padded) = padded else {
+            panic!("Expected an opaque image");
+        };
+        assert_eq!((8, 4), padded.dimensions());
+        assert_eq!(&image::Rgb([255, 255, 255]), padded.get_pixel(1, 1));
+        assert_eq!(&image::Rgb([255, 0, 0]), padded.get_pixel(2, 1));
+        assert_eq!(&image::Rgb([255, 255, 255]), padded.get_pixel(3, 0));
+
+        let left = pad(8, 2, false, HorizontalAlign::Left, white)
+            .await
+            .to_rgb8();
+        assert_eq!(&image::Rgb([255, 0, 0]), left.get_pixel(0, 0));
+        assert_eq!(&image::Rgb([255, 255, 255]), left.get_pixel(7, 0));
+
+        // The adaptive box gets flipped to match the landscape image, and sides of 0 are kept
+        let flipped = pad(4, 8, true, HorizontalAlign::Center, white).await;
+        assert_eq!((8, 4), (flipped.width(), flipped.height()));
+        let kept = pad(0, 6, false, HorizontalAlign::Center, white).await;
+        assert_eq!((4, 6), (kept.width(), kept.height()));
+
+        let transparent = pad(8, 4, false, HorizontalAlign::Center, Fill::Transparent).await;
+        assert_eq!(0, transparent.to_rgba8().get_pixel(0, 0)[3]);
+        let blurred = pad(8, 4, false, HorizontalAlign::Center, Fill::Blur).await;
+        assert!(!blurred.color().has_alpha());
+        assert_eq!(&image::Rgb([255, 0, 0]), blurred.to_rgb8().get_pixel(0, 0));
+        let auto = pad(8, 4, false, HorizontalAlign::Center, Fill::Auto).await;
+        assert_eq!(&image::Rgb([255, 0, 0]), auto.to_rgb8().get_pixel(0, 0));
+    }
+
+    #[test]
+    fn test_dominant_edge_colour() {
+        // A white border with a grey subject touching one edge
+        let image = DynamicImage::ImageRgb8(image::RgbImage::from_fn(10, 10, |x, y| {
+            if x >= 3 && y >= 3 {
+                image::Rgb([100, 100, 100])
+            } else {
+                image::Rgb([250 + (x % 2) as u8, 255, 255])
+            }
+        }));
+        assert_eq!(Colour([250, 255, 255, 255]), dominant_edge_colour(&image));
+
+        // Worked out once for every frame of an animation
+        let operations = Operations(vec![Operation::Rotate {
+            degrees: Decimal::from_hundredths(4_500),
+            fill: Fill::Auto,
+        }]);
+        assert_eq!(
+            vec![Operation::Rotate {
+                degrees: Decimal::from_hundredths(4_500),
+                fill: Fill::Colour(Colour([250, 255, 255, 255]))
+            }],
+            operations.pinned_to(&image).0
+        );
+    }
+
+    #[test]
+    fn test_colour_from_str() -> anyhow::Result<()> {
+        assert_eq!(Colour([255, 0, 51, 255]), "f03".parse()?);
+        assert_eq!(Colour([0, 255, 0, 128]), "00FF0080".parse()?);
+        assert_eq!("00ff0080", Colour([0, 255, 0, 128]).hex());
+        assert_eq!("ff0033", Colour([255, 0, 51, 255]).hex());
+        assert_eq!(
+            "Invalid colour [red], expected hex like ff0000",
+            "red".parse::<Colour>().err().unwrap().to_string()
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_operations_runner_rotate() {
+        // Red on the left, blue on the right
+        let image = DynamicImage::ImageRgb8(image::RgbImage::from_fn(2, 1, |x, _| {
+            image::Rgb(if x == 0 { [255, 0, 0] } else { [0, 0, 255] })
+        }));
+        let run = |operation| {
+            let image = image.clone();
+            async move {
+                SingletonOperationsRunner
+                    .run(image, &Operations(vec![operation]))
+                    .await
+            }
+        };
+
+        // Counter-clockwise, so the right side ends up on top
+        let rotated = run(Operation::Rotate90).await.to_rgb8();
+        assert_eq!((1, 2), rotated.dimensions());
+        assert_eq!(&image::Rgb([0, 0, 255]), rotated.get_pixel(0, 0));
+        let rotated = run(Operation::Rotate270).await.to_rgb8();
+        assert_eq!(&image::Rgb([255, 0, 0]), rotated.get_pixel(0, 0));
+
+        // Any other angle grows the canvas, filling the corners
+        let big =
+            DynamicImage::ImageRgb8(image::RgbImage::from_pixel(20, 10, image::Rgb([0, 0, 0])));
+        let filled = rotate(&big, 45.0, Fill::Colour(Colour([255, 255, 255, 255])));
+        let DynamicImage::ImageRgb8(filled) = filled else {
+            panic!("Expected an opaque image");
+        };
+        assert_eq!((21, 21), filled.dimensions());
+        assert_eq!(&image::Rgb([255, 255, 255]), filled.get_pixel(0, 0));
+        assert_eq!(&image::Rgb([0, 0, 0]), filled.get_pixel(10, 10));
+
+        let transparent = rotate(&big, 30.0, Fill::Transparent);
+        assert!(transparent.color().has_alpha());
+        assert_eq!(0, transparent.to_rgba8().get_pixel(0, 0)[3]);
+    }
+
+    #[test]
+    fn test_operations_scaled() {
+        let operations = Operations(vec![
+            Operation::Crop {
+                left: 1,
+                top: 2,
+                right: 10,
+                bottom: 20,
+            },
+            Operation::Resize {
+                width: 50,
+                height: 60,
+                focal: Some(FocalRegion {
+                    left: 3,
+                    top: 4,
+                    right: 5,
+                    bottom: 6,
+                }),
+                mode: ResizeMode::Cover,
+                horizontal_align: HorizontalAlign::Center,
+                vertical_align: VerticalAlign::Middle,
+                smart: false,
+            },
+        ]);
+        assert_eq!(
+            Operations(vec![
+                Operation::Crop {
+                    left: 3,
+                    top: 6,
+                    right: 30,
+                    bottom: 60,
+                },
+                Operation::Resize {
+                    width: 50,
+                    height: 60,
+                    focal: Some(FocalRegion {
+                        left: 9,
+                        top: 12,
+                        right: 15,
+                        bottom: 18,
+                    }),
+                    mode: ResizeMode::Cover,
+                    horizontal_align: HorizontalAlign::Center,
+                    vertical_align: VerticalAlign::Middle,
+                    smart: false,
+                },
+            ]),
+            operations.scaled(3.0)
+        );
+    }
+
+    #[test]
+    fn test_operations_try_from_path_with_no_autorotate() -> anyhow::Result<()> {
+        let path: ImageProcessingPath =
+            "trim/filters:grayscale():no_autorotate()/https://beachape.com/images/lol.jpg"
+                .parse()?;
+        let r = Operations::try_from(&path).map_err(|e| anyhow::anyhow!("{:?}", e.0))?;
+        assert_eq!(Operation::NoAutorotate, r.0[0]);
+        assert!(!r.autorotates());
+
+        let path: ImageProcessingPath = "https://beachape.com/images/lol.jpg".parse()?;
+        let r = Operations::try_from(&path).map_err(|e| anyhow::anyhow!("{:?}", e.0))?;
+        assert!(r.autorotates());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_operations_runner_run_frames() {
+        // Red block on white that moves to the right in the second frame
+        let frame = |offset: u32| {
+            let image = image::RgbaImage::from_fn(60, 40, |x, y| {
+                if (10 + offset..30 + offset).contains(&x) && (10..30).contains(&y) {
+                    image::Rgba([255, 0, 0, 255])
+                } else {
+                    image::Rgba([255, 255, 255, 255])
+                }
+            });
+            Frame::from_parts(image, 0, 0, image::Delay::from_numer_denom_ms(50, 1))
+        };
+        let operations = Operations(vec![
+            Operation::Trim {
+                from: TrimFrom::TopLeft,
+                tolerance: 0,
+            },
+            Operation::Resize {
+                width: 10,
+                height: 10,
+                mode: ResizeMode::Cover,
+                horizontal_align: HorizontalAlign::Center,
+                vertical_align: VerticalAlign::Middle,
+                focal: None,
+                smart: true,
+            },
+        ]);
+        let result = SingletonOperationsRunner
+            .run_frames(vec![frame(0), frame(10)], &operations)
+            .await;
+        assert_eq!(2, result.len());
+        // The trim is worked out on the first frame, so both frames are cut the same way
+        for processed in &result {
+            assert_eq!((10, 10), processed.buffer().dimensions());
+            assert_eq!(image::Delay::from_numer_denom_ms(50, 1), processed.delay());
+        }
+        assert_eq!(
+            &image::Rgba([255, 0, 0, 255]),
+            result[0].buffer().get_pixel(5, 5)
+        );
+        assert_ne!(
+            &image::Rgba([255, 0, 0, 255]),
+            result[1].buffer().get_pixel(0, 5)
+        );
+    }
 }
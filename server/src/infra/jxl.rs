@@ -0,0 +1,157 @@
+use std::io::Cursor;
+
+use image::error::{DecodingError, ImageFormatHint};
+use image::metadata::Orientation;
+use image::{DynamicImage, ImageDecoder, ImageError, ImageResult};
+use jxl_oxide::integration::JxlDecoder;
+use jxl_oxide::{InitializeResult, JxlImage};
+
+use super::image_encoding::ImageMetadata;
+
+const JXL_MIME_TYPE: &str = "image/jxl";
+const JXL_EXTENSION: &str = ".jxl";
+// A bare codestream, or one wrapped in the ISOBMFF container (that can also hold EXIF)
+const JXL_CODESTREAM_SIGNATURE: &[u8] = b"\xff\x0a";
+const JXL_CONTAINER_SIGNATURE: &[u8] = b"\x00\x00\x00\x0cJXL \x0d\x0a\x87\x0a";
+
+/// Whether the source is JPEG XL, going by its content type, extension or (failing those) its
+/// signature
+pub fn is_jxl(bytes: &[u8], content_type: Option<&str>, image_url: &str) -> bool {
+    if content_type.is_some_and(|t| t.starts_with(JXL_MIME_TYPE)) {
+        return true;
+    }
+    let path = image_url
+        .split(['?', '#'])
+        .next()
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    path.ends_with(JXL_EXTENSION)
+        || bytes.starts_with(JXL_CODESTREAM_SIGNATURE)
+        || bytes.starts_with(JXL_CONTAINER_SIGNATURE)
+}
+
+/// Decodes the (first frame of the) image, turned the way the file says unless told not to, along
+/// with its metadata. Like HEIC's, JPEG XL's orientation is in the codestream rather than the EXIF.
+pub fn decode_jxl(bytes: &[u8], autorotate: bool) -> ImageResult<(DynamicImage, ImageMetadata)> {
+    let orientation = orientation(bytes)?;
+    let mut decoder = JxlDecoder::new(Cursor::new(bytes))?;
+    let icc_profile = decoder.icc_profile()?;
+    // The decoder always applies the orientation, so it's undone if it's meant to be ignored
+    let mut image = DynamicImage::from_decoder(decoder)?;
+    if !autorotate {
+        image.apply_orientation(inverse(orientation));
+    }
+    let exif = exif_box(bytes).and_then(without_tiff_header_offset);
+    Ok((image, ImageMetadata { icc_profile, exif }))
+}
+
+fn orientation(bytes: &[u8]) -> ImageResult<Orientation> {
+    let mut uninitialised = JxlImage::builder().build_uninit();
+    uninitialised.feed_bytes(bytes).map_err(decoding_error)?;
+    match uninitialised.try_init().map_err(decoding_error)? {
+        InitializeResult::Initialized(image) => {
+            let orientation = image.image_header().metadata.orientation;
+            Ok(Orientation::from_exif(orientation as u8).unwrap_or(Orientation::NoTransforms))
+        }
+        InitializeResult::NeedMoreData(_) => Err(decoding_error("Truncated JPEG XL header")),
+    }
+}
+
+fn decoding_error(error: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> ImageError {
+    ImageError::Decoding(DecodingError::new(
+        ImageFormatHint::Name("JPEG XL".to_string()),
+        error,
+    ))
+}
+
+// Rotating by a quarter turn one way is undone by turning the other way, the rest are their own
+// inverse
+fn inverse(orientation: Orientation) -> Orientation {
+    match orientation {
+        Orientation::Rotate90 => Orientation::Rotate270,
+        Orientation::Rotate270 => Orientation::Rotate90,
+        other => other,
+    }
+}
+
+// The contents of the (uncompressed) `Exif` box, if the codestream is in a container with one
+fn exif_box(bytes: &[u8]) -> Option<&[u8]> {
+    if !bytes.starts_with(JXL_CONTAINER_SIGNATURE) {
+        return None;
+    }
+    let mut rest = &bytes[JXL_CONTAINER_SIGNATURE.len()..];
+    while rest.len() >= 8 {
+        let size = u32::from_be_bytes(rest[..4].try_into().ok()?) as u64;
+        let box_type = &rest[4..8];
+        // A size of 1 means a 64 bit size follows the type, 0 that the box runs to the end
+        let (header_length, size) = match size {
+            1 => (16, u64::from_be_bytes(rest.get(8..16)?.try_into().ok()?)),
+            0 => (8, rest.len() as u64),
+            size => (8, size),
+        };
+        let size = usize::try_from(size).ok()?;
+        if size < header_length || size > rest.len() {
+            return None;
+        }
+        if box_type == b"Exif" {
+            return Some(&rest[header_length..size]);
+        }
+        rest = &rest[size..];
+    }
+    None
+}
+
+// Like HEIF's, JPEG XL EXIF boxes start with the offset of the TIFF header
+fn without_tiff_header_offset(block: &[u8]) -> Option<Vec<u8>> {
+    let offset = u32::from_be_bytes(block.get(..4)?.try_into().ok()?) as usize;
+    let mut exif = block.get(4 + offset..)?.to_vec();
+    // The codestream's orientation is the one that counts, and it's already been dealt with
+    let _ = Orientation::remove_from_exif_chunk(&mut exif);
+    Some(exif)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::GenericImageView;
+
+    #[test]
+    fn test_is_jxl() {
+        assert!(is_jxl(b"", Some("image/jxl"), "https://a.com/photo"));
+        assert!(is_jxl(b"", None, "https://a.com/photo.JXL?v=1"));
+        assert!(is_jxl(b"\xff\x0a\x08\x00", None, "https://a.com/photo"));
+        let container = b"\x00\x00\x00\x0cJXL \x0d\x0a\x87\x0a\x00\x00\x00\x14ftypjxl ";
+        assert!(is_jxl(container, None, "https://a.com/photo"));
+        assert!(!is_jxl(
+            b"\xff\xd8\xff\xe0",
+            None,
+            "https://a.com/photo.jpg"
+        ));
+    }
+
+    #[test]
+    fn test_decode_jxl() {
+        let (image, metadata) = decode_jxl(include_bytes!("colours.jxl"), true).unwrap();
+        assert_eq!((4, 2), image.dimensions());
+        assert_eq!([255, 0, 0, 255], image.get_pixel(0, 0).0);
+        assert_eq!([0, 255, 255, 255], image.get_pixel(3, 1).0);
+        assert!(metadata.exif.is_none());
+    }
+
+    #[test]
+    fn test_exif_box() {
+        let exif = b"\x00\x00\x00\x02xxMM\x00\x2a\x00\x00\x00\x08\x00\x00";
+        let mut container = JXL_CONTAINER_SIGNATURE.to_vec();
+        container.extend_from_slice(b"\x00\x00\x00\x14ftypjxl \x00\x00\x00\x00jxl ");
+        container.extend_from_slice(&(8 + exif.len() as u32).to_be_bytes());
+        container.extend_from_slice(b"Exif");
+        container.extend_from_slice(exif);
+        container.extend_from_slice(b"\x00\x00\x00\x0ajxlc\xff\x0a");
+        assert_eq!(Some(&exif[..]), exif_box(&container));
+        assert_eq!(
+            Some(b"MM\x00\x2a\x00\x00\x00\x08\x00\x00".to_vec()),
+            exif_box(&container).and_then(without_tiff_header_offset)
+        );
+        assert_eq!(None, exif_box(b"\xff\x0a\x08\x00"));
+    }
+}
@@ -1,6 +1,16 @@
+pub mod animation;
+pub mod colour_profiles;
 pub mod components;
 pub mod config;
 pub mod errors;
+pub mod filters;
+#[cfg(feature = "heic")]
+pub mod heic;
 pub mod image_caching;
+pub mod image_encoding;
 pub mod image_manipulation;
+#[cfg(feature = "jxl")]
+pub mod jxl;
+pub mod smart_crop;
+pub mod svg;
 pub mod validations;
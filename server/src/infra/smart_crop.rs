@@ -0,0 +1,103 @@
+use image::{DynamicImage, GrayImage};
+use serde::{Deserialize, Serialize};
+
+/// The point (in pixels of the analysed image) that smart cropping considers the most
+/// interesting, and therefore keeps as close to the center of a cover resize as possible
+#[derive(PartialEq, Eq, Debug, Serialize, Deserialize, Clone, Copy)]
+pub struct FocalPoint {
+    pub x: u32,
+    pub y: u32,
+}
+
+// Saliency is estimated on a thumbnail no larger than this, which keeps detection cheap
+// regardless of the source size
+const ANALYSIS_SIZE: u32 = 100;
+
+/// Finds the "centre of mass" of the detail in the image: a Sobel edge map of a small
+/// greyscale copy, weighted by gradient magnitude. Flat images fall back to the center.
+pub fn detect_focal_point(image: &DynamicImage) -> FocalPoint {
+    let (width, height) = (image.width(), image.height());
+    let center = FocalPoint {
+        x: width / 2,
+        y: height / 2,
+    };
+    if width < 3 || height < 3 {
+        return center;
+    }
+    let luma = if width > ANALYSIS_SIZE || height > ANALYSIS_SIZE {
+        image.thumbnail(ANALYSIS_SIZE, ANALYSIS_SIZE).to_luma8()
+    } else {
+        image.to_luma8()
+    };
+    match edge_centroid(&luma) {
+        Some((x, y)) => {
+            let to_source = |v: f64, analysed: u32, source: u32| {
+                (((v + 0.5) * source as f64 / analysed as f64) as u32).min(source - 1)
+            };
+            FocalPoint {
+                x: to_source(x, luma.width(), width),
+                y: to_source(y, luma.height(), height),
+            }
+        }
+        None => center,
+    }
+}
+
+fn edge_centroid(luma: &GrayImage) -> Option<(f64, f64)> {
+    let (width, height) = luma.dimensions();
+    if width < 3 || height < 3 {
+        return None;
+    }
+    let at = |x: u32, y: u32| luma.get_pixel(x, y).0[0] as f64;
+    let (mut total, mut sum_x, mut sum_y) = (0.0, 0.0, 0.0);
+    for y in 1..height - 1 {
+        for x in 1..width - 1 {
+            let gx = at(x + 1, y - 1) + 2.0 * at(x + 1, y) + at(x + 1, y + 1)
+                - at(x - 1, y - 1)
+                - 2.0 * at(x - 1, y)
+                - at(x - 1, y + 1);
+            let gy = at(x - 1, y + 1) + 2.0 * at(x, y + 1) + at(x + 1, y + 1)
+                - at(x - 1, y - 1)
+                - 2.0 * at(x, y - 1)
+                - at(x + 1, y - 1);
+            let magnitude = (gx * gx + gy * gy).sqrt();
+            total += magnitude;
+            sum_x += magnitude * x as f64;
+            sum_y += magnitude * y as f64;
+        }
+    }
+    if total > 0.0 {
+        Some((sum_x / total, sum_y / total))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgb, RgbImage};
+
+    use super::*;
+
+    #[test]
+    fn test_detect_focal_point_flat_image_is_centered() {
+        let image = DynamicImage::ImageRgb8(RgbImage::from_pixel(40, 20, Rgb([120, 120, 120])));
+        assert_eq!(FocalPoint { x: 20, y: 10 }, detect_focal_point(&image));
+    }
+
+    #[test]
+    fn test_detect_focal_point_follows_detail() {
+        // A flat canvas with a checkerboard patch in the bottom-right corner
+        let mut canvas = RgbImage::from_pixel(400, 200, Rgb([255, 255, 255]));
+        for y in 140..180 {
+            for x in 320..380 {
+                if (x / 4 + y / 4) % 2 == 0 {
+                    canvas.put_pixel(x, y, Rgb([0, 0, 0]));
+                }
+            }
+        }
+        let point = detect_focal_point(&DynamicImage::ImageRgb8(canvas));
+        assert!((320..380).contains(&point.x), "{point:?}");
+        assert!((140..180).contains(&point.y), "{point:?}");
+    }
+}
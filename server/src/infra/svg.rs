@@ -0,0 +1,218 @@
+use image::{DynamicImage, RgbaImage};
+use resvg::{tiny_skia, usvg};
+
+use super::image_manipulation::{Operation, Operations, ResizeMode};
+
+const SVG_MIME_TYPE: &str = "image/svg+xml";
+
+/// Whether the source is an SVG, going by its content type, extension or (failing those) contents
+pub fn is_svg(bytes: &[u8], content_type: Option<&str>, image_url: &str) -> bool {
+    if let Some(content_type) = content_type {
+        if content_type.starts_with(SVG_MIME_TYPE) {
+            return true;
+        }
+    }
+    let path = image_url.split(['?', '#']).next().unwrap_or_default();
+    if path.to_ascii_lowercase().ends_with(".svg") {
+        return true;
+    }
+    // Sniffing only looks at the start, which is where the root element (or the XML declaration
+    // and comments in front of it) should be
+    let start = String::from_utf8_lossy(&bytes[..bytes.len().min(1024)]);
+    let start = start.trim_start_matches('\u{feff}').trim_start();
+    start.starts_with("<svg") || (start.starts_with("<?xml") && start.contains("<svg"))
+}
+
+/// Parses the SVG without loading anything it refers to: images (whether external or embedded
+/// as data URLs) are dropped, and text can't be rendered as no fonts are loaded
+pub fn parse_svg(bytes: &[u8]) -> Result<usvg::Tree, usvg::Error> {
+    let options = usvg::Options {
+        resources_dir: None,
+        image_href_resolver: usvg::ImageHrefResolver {
+            resolve_data: Box::new(|_, _, _| None),
+            resolve_string: Box::new(|_, _| None),
+        },
+        ..Default::default()
+    };
+    usvg::Tree::from_data(bytes, &options)
+}
+
+/// How many nodes rendering the SVG means going through, once `use` references are expanded
+pub fn node_count(tree: &usvg::Tree) -> usize {
+    fn count(group: &usvg::Group) -> usize {
+        group
+            .children()
+            .iter()
+            .map(|node| match node {
+                usvg::Node::Group(group) => 1 + count(group),
+                _ => 1,
+            })
+            .sum()
+    }
+    count(tree.root())
+}
+
+/// How much to scale the SVG by when rendering, so that the first resize gets the pixels it needs
+/// without upscaling (and so that results stay crisp)
+pub fn render_scale(tree: &usvg::Tree, operations: &Operations) -> f32 {
+    let size = tree.size();
+    let (mut width, mut height) = (size.width(), size.height());
+    for op in &operations.0 {
+        match *op {
+            Operation::Crop {
+                left,
+                top,
+                right,
+                bottom,
+            } => {
+                width = right.saturating_sub(left) as f32;
+                height = bottom.saturating_sub(top) as f32;
+            }
+            Operation::Resize {
+                width: target_width,
+                height: target_height,
+                mode,
+                ..
+            } => {
+                if width <= 0.0 || height <= 0.0 {
+                    return 1.0;
+                }
+                let (x, y) = (target_width as f32 / width, target_height as f32 / height);
+                return match (target_width, target_height) {
+                    (0, 0) => 1.0,
+                    (_, 0) => x,
+                    (0, _) => y,
+                    _ => match mode {
+                        ResizeMode::FitIn => x.min(y),
+                        ResizeMode::AdaptiveFitIn => {
+                            let flipped =
+                                (target_height as f32 / width).min(target_width as f32 / height);
+                            x.min(y).max(flipped)
+                        }
+                        _ => x.max(y),
+                    },
+                };
+            }
+            _ => {}
+        }
+    }
+    1.0
+}
+
+/// The size in pixels of the SVG rendered at the scale
+pub fn render_size(tree: &usvg::Tree, scale: f32) -> (u32, u32) {
+    let size = tree.size();
+    (
+        (size.width() * scale).ceil().max(1.0) as u32,
+        (size.height() * scale).ceil().max(1.0) as u32,
+    )
+}
+
+/// Renders the SVG at the scale, on a transparent background
+pub fn render_svg(tree: &usvg::Tree, scale: f32) -> Option<DynamicImage> {
+    let (width, height) = render_size(tree, scale);
+    let mut pixmap = tiny_skia::Pixmap::new(width, height)?;
+    resvg::render(
+        tree,
+        tiny_skia::Transform::from_scale(scale, scale),
+        &mut pixmap.as_mut(),
+    );
+    // tiny-skia works with premultiplied alpha
+    let pixels = pixmap
+        .pixels()
+        .iter()
+        .flat_map(|pixel| {
+            let colour = pixel.demultiply();
+            [colour.red(), colour.green(), colour.blue(), colour.alpha()]
+        })
+        .collect();
+    RgbaImage::from_raw(width, height, pixels).map(DynamicImage::ImageRgba8)
+}
+
+#[cfg(test)]
+mod tests {
+    use image::Rgba;
+
+    use super::*;
+    use crate::infra::image_manipulation::{HorizontalAlign, VerticalAlign};
+
+    const LOGO: &[u8] = br#"<?xml version="1.0"?>
+<svg xmlns="http://www.w3.org/2000/svg" xmlns:xlink="http://www.w3.org/1999/xlink" width="20" height="10">
+  <rect x="0" y="0" width="10" height="10" fill="red"/>
+  <rect x="10" y="0" width="10" height="10" fill="blue"/>
+  <image href="file:///etc/passwd" width="20" height="10"/>
+</svg>"#;
+
+    fn resize(width: u32, height: u32, mode: ResizeMode) -> Operations {
+        Operations(vec![Operation::Resize {
+            width,
+            height,
+            mode,
+            horizontal_align: HorizontalAlign::Center,
+            vertical_align: VerticalAlign::Middle,
+            focal: None,
+            smart: false,
+        }])
+    }
+
+    #[test]
+    fn test_is_svg() {
+        assert!(is_svg(b"", Some("image/svg+xml"), "https://a.com/logo"));
+        assert!(is_svg(b"", None, "https://a.com/logo.SVG?v=2"));
+        assert!(is_svg(LOGO, None, "https://a.com/logo"));
+        assert!(is_svg(b"\n  <svg></svg>", None, "https://a.com/logo"));
+        assert!(!is_svg(
+            b"\x89PNG",
+            Some("image/png"),
+            "https://a.com/logo.png"
+        ));
+    }
+
+    #[test]
+    fn test_render_svg_at_target_size() {
+        let tree = parse_svg(LOGO).unwrap();
+        // The image that refers to a local file is dropped
+        assert_eq!(2, node_count(&tree));
+
+        let scale = render_scale(&tree, &resize(200, 0, ResizeMode::Cover));
+        assert_eq!(10.0, scale);
+        assert_eq!((200, 100), render_size(&tree, scale));
+        let image = render_svg(&tree, scale).unwrap().to_rgba8();
+        assert_eq!((200, 100), image.dimensions());
+        assert_eq!(&Rgba([255, 0, 0, 255]), image.get_pixel(50, 50));
+        assert_eq!(&Rgba([0, 0, 255, 255]), image.get_pixel(150, 50));
+    }
+
+    #[test]
+    fn test_render_scale() {
+        let tree = parse_svg(LOGO).unwrap();
+        assert_eq!(1.0, render_scale(&tree, &Operations(Vec::new())));
+        assert_eq!(
+            10.0,
+            render_scale(&tree, &resize(100, 100, ResizeMode::Cover))
+        );
+        assert_eq!(
+            5.0,
+            render_scale(&tree, &resize(100, 100, ResizeMode::FitIn))
+        );
+        assert_eq!(2.0, render_scale(&tree, &resize(0, 20, ResizeMode::FitIn)));
+
+        // Crops are taken into account
+        let mut operations = resize(100, 100, ResizeMode::Cover);
+        operations.0.insert(
+            0,
+            Operation::Crop {
+                left: 0,
+                top: 0,
+                right: 5,
+                bottom: 5,
+            },
+        );
+        assert_eq!(20.0, render_scale(&tree, &operations));
+    }
+
+    #[test]
+    fn test_parse_svg_rejects_garbage() {
+        assert!(parse_svg(b"<svg").is_err());
+    }
+}
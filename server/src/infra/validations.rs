@@ -1,7 +1,11 @@
 use bytesize::ByteSize;
-use image::DynamicImage;
+use image::{DynamicImage, ImageFormat};
 
-use super::{config::ValidationSettings, image_manipulation::Operations};
+use super::{
+    config::ValidationSettings,
+    image_encoding::OutputOptions,
+    image_manipulation::{padded_size, resized_size, rotated_size, trim_bounds, Operations},
+};
 
 pub trait Validator {
     fn validate_operations(
@@ -10,12 +14,54 @@ pub trait Validator {
         operations: &Operations,
     ) -> Result<(), ValidationErrors>;
 
+    fn validate_output(
+        &self,
+        settings: &ValidationSettings,
+        output: &OutputOptions,
+    ) -> Result<(), ValidationErrors>;
+
     fn validate_source_image(
         &self,
         settings: &ValidationSettings,
         image: &DynamicImage,
     ) -> Result<(), ValidationErrors>;
 
+    fn validate_frame_count(
+        &self,
+        settings: &ValidationSettings,
+        frame_count: usize,
+    ) -> Result<(), ValidationErrors>;
+
+    fn validate_svg(
+        &self,
+        settings: &ValidationSettings,
+        node_count: usize,
+        render_size: (u32, u32),
+    ) -> Result<(), ValidationErrors>;
+
+    // For checks on the encoded image, before it gets cached and served
+    fn validate_encoded_size(
+        &self,
+        output: &OutputOptions,
+        encoded_size: usize,
+    ) -> Result<(), ValidationErrors>;
+
+    // For checks on the processed image, before it gets encoded
+    fn validate_encoding(
+        &self,
+        settings: &ValidationSettings,
+        format: ImageFormat,
+        image: &DynamicImage,
+    ) -> Result<(), ValidationErrors>;
+
+    // For checks that can only be done once we know what the source image looks like
+    fn validate_operations_against_source_image(
+        &self,
+        settings: &ValidationSettings,
+        operations: &Operations,
+        image: &DynamicImage,
+    ) -> Result<(), ValidationErrors>;
+
     fn validate_image_download_size(
         &self,
         settings: &ValidationSettings,
@@ -29,6 +75,9 @@ pub trait Validator {
     ) -> Result<(), ValidationErrors>;
 }
 
+// The largest possible distance between two RGBA colours, anything beyond it trims everything
+const MAX_TRIM_TOLERANCE: u16 = 510;
+
 pub struct SingletonValidator;
 
 pub struct ValidationErrors(pub Vec<String>);
@@ -43,7 +92,49 @@ impl Validator for SingletonValidator {
             .0
             .iter()
             .fold(Vec::new(), |mut next, op| match *op {
-                crate::infra::image_manipulation::Operation::Resize { width, height } => {
+                crate::infra::image_manipulation::Operation::Trim { tolerance, .. } => {
+                    if tolerance > MAX_TRIM_TOLERANCE {
+                        next.push(format!(
+                            "Trim tolerance [{tolerance}] too large, must be [{MAX_TRIM_TOLERANCE}] or lower"
+                        ));
+                    }
+                    next
+                }
+                crate::infra::image_manipulation::Operation::Crop {
+                    left,
+                    top,
+                    right,
+                    bottom,
+                } => {
+                    if right <= left || bottom <= top {
+                        next.push(format!(
+                            "Crop box [{left}x{top}:{right}x{bottom}] is empty, the bottom-right corner must be below and to the right of the top-left corner"
+                        ));
+                    }
+                    if right > settings.max_source_image_width
+                        || bottom > settings.max_source_image_height
+                    {
+                        next.push(format!(
+                            "Crop box [{left}x{top}:{right}x{bottom}] too large, must fit in [{}x{}]",
+                            settings.max_source_image_width, settings.max_source_image_height
+                        ));
+                    }
+                    next
+                }
+                crate::infra::image_manipulation::Operation::Resize {
+                    width,
+                    height,
+                    focal,
+                    ..
+                } => {
+                    if let Some(focal) = focal {
+                        if focal.right <= focal.left || focal.bottom <= focal.top {
+                            next.push(format!(
+                                "Focal region [{}x{}:{}x{}] is empty, the bottom-right corner must be below and to the right of the top-left corner",
+                                focal.left, focal.top, focal.right, focal.bottom
+                            ));
+                        }
+                    }
                     if width > settings.max_resize_target_width {
                         next.push(format!(
                             "Resize target width [{width}] too large, must be [{}] or lower",
@@ -60,6 +151,16 @@ impl Validator for SingletonValidator {
                 }
                 crate::infra::image_manipulation::Operation::FlipHorizontally => next,
                 crate::infra::image_manipulation::Operation::FlipVertically => next,
+                crate::infra::image_manipulation::Operation::Rotate90
+                | crate::infra::image_manipulation::Operation::Rotate180
+                | crate::infra::image_manipulation::Operation::Rotate270
+                | crate::infra::image_manipulation::Operation::Rotate { .. }
+                | crate::infra::image_manipulation::Operation::Pad { .. } => next,
+                crate::infra::image_manipulation::Operation::NoAutorotate => next,
+                crate::infra::image_manipulation::Operation::Filter(ref filter) => {
+                    next.extend(filter.validate(settings));
+                    next
+                }
             });
         if problems.is_empty() {
             Ok(())
@@ -68,6 +169,27 @@ impl Validator for SingletonValidator {
         }
     }
 
+    fn validate_output(
+        &self,
+        _settings: &ValidationSettings,
+        output: &OutputOptions,
+    ) -> Result<(), ValidationErrors> {
+        let mut problems = Vec::new();
+        if let Some(quality) = output.quality {
+            if !(1..=100).contains(&quality) {
+                problems.push(format!("Quality [{quality}] must be between 1 and 100"));
+            }
+        }
+        if output.max_bytes == Some(0) {
+            problems.push("Max bytes must be more than [0]".to_string());
+        }
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ValidationErrors(problems))
+        }
+    }
+
     fn validate_source_image(
         &self,
         settings: &ValidationSettings,
@@ -95,6 +217,178 @@ impl Validator for SingletonValidator {
         }
     }
 
+    fn validate_frame_count(
+        &self,
+        settings: &ValidationSettings,
+        frame_count: usize,
+    ) -> Result<(), ValidationErrors> {
+        if frame_count > settings.max_frames as usize {
+            Err(ValidationErrors(vec![format!(
+                "Source image has too many frames, must have [{}] or fewer",
+                settings.max_frames
+            )]))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn validate_svg(
+        &self,
+        settings: &ValidationSettings,
+        node_count: usize,
+        (width, height): (u32, u32),
+    ) -> Result<(), ValidationErrors> {
+        let mut problems = Vec::new();
+        if node_count > settings.max_svg_nodes as usize {
+            problems.push(format!(
+                "SVG has [{node_count}] nodes, must have [{}] or fewer",
+                settings.max_svg_nodes
+            ));
+        }
+        if width as u64 * height as u64 > settings.max_svg_render_pixels as u64 {
+            problems.push(format!(
+                "SVG would be rendered at [{width}x{height}], must be [{}] pixels or fewer",
+                settings.max_svg_render_pixels
+            ));
+        }
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ValidationErrors(problems))
+        }
+    }
+
+    fn validate_encoded_size(
+        &self,
+        output: &OutputOptions,
+        encoded_size: usize,
+    ) -> Result<(), ValidationErrors> {
+        match output.max_bytes {
+            Some(max_bytes) if encoded_size > max_bytes as usize => {
+                Err(ValidationErrors(vec![format!(
+                    "Could not get the image down to [{max_bytes}] bytes, the smallest was [{encoded_size}] bytes"
+                )]))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn validate_encoding(
+        &self,
+        settings: &ValidationSettings,
+        format: ImageFormat,
+        image: &DynamicImage,
+    ) -> Result<(), ValidationErrors> {
+        let pixels = image.width() as u64 * image.height() as u64;
+        if format == ImageFormat::Avif && pixels > settings.max_avif_output_pixels as u64 {
+            Err(ValidationErrors(vec![format!(
+                "Image of [{}x{}] too large to encode as AVIF, must be [{}] pixels or fewer",
+                image.width(),
+                image.height(),
+                settings.max_avif_output_pixels
+            )]))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn validate_operations_against_source_image(
+        &self,
+        settings: &ValidationSettings,
+        operations: &Operations,
+        image: &DynamicImage,
+    ) -> Result<(), ValidationErrors> {
+        // Follows the size of the image through the operations, as that's what crops and
+        // resizes work on, and rotating and padding can grow it past the limits
+        let (mut width, mut height) = (image.width(), image.height());
+        let mut trimmed = false;
+        let mut problems = Vec::new();
+        let too_large = |what: String, width: u32, height: u32| {
+            (width > settings.max_resize_target_width
+                || height > settings.max_resize_target_height)
+                .then(|| {
+                    format!(
+                        "{what} comes out at [{width}x{height}], too large, must be [{}x{}] or lower",
+                        settings.max_resize_target_width, settings.max_resize_target_height
+                    )
+                })
+        };
+        for op in &operations.0 {
+            match *op {
+                crate::infra::image_manipulation::Operation::Trim { from, tolerance } => {
+                    // Trimming only ever comes first, so it works on the source image itself
+                    let (_, _, trimmed_width, trimmed_height) = trim_bounds(image, from, tolerance);
+                    (width, height) = (trimmed_width, trimmed_height);
+                    trimmed = true;
+                }
+                crate::infra::image_manipulation::Operation::Crop {
+                    left,
+                    top,
+                    right,
+                    bottom,
+                } => {
+                    if right > width || bottom > height {
+                        problems.push(format!(
+                            "Crop box [{left}x{top}:{right}x{bottom}] is outside of the {} image [{width}x{height}]",
+                            if trimmed { "trimmed" } else { "source" }
+                        ));
+                    }
+                    (width, height) = (right.saturating_sub(left), bottom.saturating_sub(top));
+                }
+                crate::infra::image_manipulation::Operation::Resize {
+                    width: target_width,
+                    height: target_height,
+                    mode,
+                    ..
+                } => {
+                    // A side left at 0 follows the aspect ratio, which can make it huge
+                    let (resized_width, resized_height) =
+                        resized_size(width, height, target_width, target_height, mode);
+                    if resized_width > settings.max_resize_target_width
+                        || resized_height > settings.max_resize_target_height
+                    {
+                        problems.push(format!(
+                            "Resize to [{target_width}x{target_height}] comes out at [{resized_width}x{resized_height}], too large, must be [{}x{}] or lower",
+                            settings.max_resize_target_width, settings.max_resize_target_height
+                        ));
+                    }
+                    (width, height) = (resized_width, resized_height);
+                }
+                crate::infra::image_manipulation::Operation::Rotate90
+                | crate::infra::image_manipulation::Operation::Rotate270 => {
+                    (width, height) = (height, width);
+                }
+                crate::infra::image_manipulation::Operation::Rotate { degrees, .. } => {
+                    (width, height) = rotated_size(width, height, degrees.as_f32());
+                    problems.extend(too_large(
+                        format!("Rotating by [{}] degrees", degrees.as_f32()),
+                        width,
+                        height,
+                    ));
+                }
+                crate::infra::image_manipulation::Operation::Pad {
+                    width: box_width,
+                    height: box_height,
+                    adaptive,
+                    ..
+                } => {
+                    (width, height) = padded_size(width, height, box_width, box_height, adaptive);
+                    problems.extend(too_large(
+                        format!("Padding to [{box_width}x{box_height}]"),
+                        width,
+                        height,
+                    ));
+                }
+                _ => {}
+            }
+        }
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ValidationErrors(problems))
+        }
+    }
+
     fn validate_image_download_size(
         &self,
         settings: &ValidationSettings,
@@ -146,6 +440,11 @@ mod tests {
             crate::infra::image_manipulation::Operation::Resize {
                 width: settings.max_resize_target_width,
                 height: settings.max_resize_target_height,
+                mode: crate::infra::image_manipulation::ResizeMode::Cover,
+                horizontal_align: crate::infra::image_manipulation::HorizontalAlign::Center,
+                vertical_align: crate::infra::image_manipulation::VerticalAlign::Middle,
+                focal: None,
+                smart: false,
             },
             crate::infra::image_manipulation::Operation::FlipHorizontally,
             crate::infra::image_manipulation::Operation::FlipVertically,
@@ -162,6 +461,11 @@ mod tests {
             crate::infra::image_manipulation::Operation::Resize {
                 width: settings.max_resize_target_width + 1,
                 height: settings.max_resize_target_height,
+                mode: crate::infra::image_manipulation::ResizeMode::Cover,
+                horizontal_align: crate::infra::image_manipulation::HorizontalAlign::Center,
+                vertical_align: crate::infra::image_manipulation::VerticalAlign::Middle,
+                focal: None,
+                smart: false,
             },
             crate::infra::image_manipulation::Operation::FlipHorizontally,
             crate::infra::image_manipulation::Operation::FlipVertically,
@@ -180,6 +484,11 @@ mod tests {
             crate::infra::image_manipulation::Operation::Resize {
                 width: settings.max_resize_target_width,
                 height: settings.max_resize_target_height + 1,
+                mode: crate::infra::image_manipulation::ResizeMode::Cover,
+                horizontal_align: crate::infra::image_manipulation::HorizontalAlign::Center,
+                vertical_align: crate::infra::image_manipulation::VerticalAlign::Middle,
+                focal: None,
+                smart: false,
             },
             crate::infra::image_manipulation::Operation::FlipHorizontally,
             crate::infra::image_manipulation::Operation::FlipVertically,
@@ -198,6 +507,11 @@ mod tests {
             crate::infra::image_manipulation::Operation::Resize {
                 width: settings.max_resize_target_width + 1,
                 height: settings.max_resize_target_height + 1,
+                mode: crate::infra::image_manipulation::ResizeMode::Cover,
+                horizontal_align: crate::infra::image_manipulation::HorizontalAlign::Center,
+                vertical_align: crate::infra::image_manipulation::VerticalAlign::Middle,
+                focal: None,
+                smart: false,
             },
             crate::infra::image_manipulation::Operation::FlipHorizontally,
             crate::infra::image_manipulation::Operation::FlipVertically,
@@ -210,6 +524,405 @@ mod tests {
         assert!(errors.0[1].starts_with("Resize target height"));
     }
 
+    #[test]
+    fn test_crop_operations_validation() {
+        let settings = ValidationSettings::default();
+        let good = Operations(vec![crate::infra::image_manipulation::Operation::Crop {
+            left: 0,
+            top: 0,
+            right: settings.max_source_image_width,
+            bottom: settings.max_source_image_height,
+        }]);
+        assert!(SingletonValidator
+            .validate_operations(&settings, &good)
+            .is_ok());
+
+        let empty = Operations(vec![crate::infra::image_manipulation::Operation::Crop {
+            left: 10,
+            top: 10,
+            right: 10,
+            bottom: 20,
+        }]);
+        let errors = SingletonValidator
+            .validate_operations(&settings, &empty)
+            .err()
+            .unwrap();
+        assert_eq!(1, errors.0.len());
+        assert!(errors.0[0].contains("is empty"));
+
+        let too_big = Operations(vec![crate::infra::image_manipulation::Operation::Crop {
+            left: 0,
+            top: 0,
+            right: settings.max_source_image_width + 1,
+            bottom: 10,
+        }]);
+        let errors = SingletonValidator
+            .validate_operations(&settings, &too_big)
+            .err()
+            .unwrap();
+        assert_eq!(1, errors.0.len());
+        assert!(errors.0[0].contains("too large"));
+    }
+
+    #[test]
+    fn test_output_validation() {
+        let settings = ValidationSettings::default();
+        let with_quality = |quality| OutputOptions {
+            quality: Some(quality),
+            ..Default::default()
+        };
+        assert!(SingletonValidator
+            .validate_output(&settings, &OutputOptions::default())
+            .is_ok());
+        assert!(SingletonValidator
+            .validate_output(&settings, &with_quality(100))
+            .is_ok());
+        for bad in [0, 101] {
+            let errors = SingletonValidator
+                .validate_output(&settings, &with_quality(bad))
+                .err()
+                .unwrap();
+            assert_eq!(
+                vec![format!("Quality [{bad}] must be between 1 and 100")],
+                errors.0
+            );
+        }
+        let no_bytes = OutputOptions {
+            max_bytes: Some(0),
+            ..Default::default()
+        };
+        let errors = SingletonValidator
+            .validate_output(&settings, &no_bytes)
+            .err()
+            .unwrap();
+        assert_eq!(vec!["Max bytes must be more than [0]"], errors.0);
+    }
+
+    #[test]
+    fn test_encoded_size_validation() {
+        let output = OutputOptions {
+            max_bytes: Some(1000),
+            ..Default::default()
+        };
+        assert!(SingletonValidator
+            .validate_encoded_size(&OutputOptions::default(), 5000)
+            .is_ok());
+        assert!(SingletonValidator
+            .validate_encoded_size(&output, 1000)
+            .is_ok());
+        let errors = SingletonValidator
+            .validate_encoded_size(&output, 1001)
+            .err()
+            .unwrap();
+        assert_eq!(
+            vec!["Could not get the image down to [1000] bytes, the smallest was [1001] bytes"],
+            errors.0
+        );
+    }
+
+    #[test]
+    fn test_frame_count_validation() {
+        let settings = ValidationSettings {
+            max_frames: 10,
+            ..Default::default()
+        };
+        assert!(SingletonValidator
+            .validate_frame_count(&settings, 10)
+            .is_ok());
+        let errors = SingletonValidator
+            .validate_frame_count(&settings, 11)
+            .err()
+            .unwrap();
+        assert_eq!(
+            vec!["Source image has too many frames, must have [10] or fewer"],
+            errors.0
+        );
+    }
+
+    #[test]
+    fn test_svg_validation() {
+        let settings = ValidationSettings {
+            max_svg_nodes: 100,
+            max_svg_render_pixels: 10_000,
+            ..Default::default()
+        };
+        assert!(SingletonValidator
+            .validate_svg(&settings, 100, (100, 100))
+            .is_ok());
+        let errors = SingletonValidator
+            .validate_svg(&settings, 101, (100, 101))
+            .err()
+            .unwrap();
+        assert_eq!(
+            vec![
+                "SVG has [101] nodes, must have [100] or fewer",
+                "SVG would be rendered at [100x101], must be [10000] pixels or fewer"
+            ],
+            errors.0
+        );
+    }
+
+    #[test]
+    fn test_encoding_validation() {
+        let settings = ValidationSettings {
+            max_avif_output_pixels: 100,
+            ..Default::default()
+        };
+        let small = DynamicImage::new_rgb8(10, 10);
+        let big = DynamicImage::new_rgb8(10, 11);
+        assert!(SingletonValidator
+            .validate_encoding(&settings, ImageFormat::Avif, &small)
+            .is_ok());
+        assert!(SingletonValidator
+            .validate_encoding(&settings, ImageFormat::Png, &big)
+            .is_ok());
+        let errors = SingletonValidator
+            .validate_encoding(&settings, ImageFormat::Avif, &big)
+            .err()
+            .unwrap();
+        assert_eq!(
+            vec!["Image of [10x11] too large to encode as AVIF, must be [100] pixels or fewer"],
+            errors.0
+        );
+    }
+
+    #[test]
+    fn test_trim_operations_validation() {
+        let settings = ValidationSettings::default();
+        let with_tolerance = |tolerance| {
+            Operations(vec![crate::infra::image_manipulation::Operation::Trim {
+                from: crate::infra::image_manipulation::TrimFrom::TopLeft,
+                tolerance,
+            }])
+        };
+        assert!(SingletonValidator
+            .validate_operations(&settings, &with_tolerance(510))
+            .is_ok());
+        let errors = SingletonValidator
+            .validate_operations(&settings, &with_tolerance(511))
+            .err()
+            .unwrap();
+        assert_eq!(1, errors.0.len());
+        assert!(errors.0[0].starts_with("Trim tolerance [511] too large"));
+    }
+
+    #[test]
+    fn test_filter_operations_validation() {
+        use crate::infra::filters::Filter;
+        use crate::infra::image_manipulation::Operation;
+
+        let settings = ValidationSettings::default();
+        let operations = Operations(vec![
+            Operation::Filter(Filter::Saturation(-100)),
+            Operation::Filter(Filter::Brightness(-101)),
+            Operation::Filter(Filter::Rgb(0, 0, 150)),
+        ]);
+        let errors = SingletonValidator
+            .validate_operations(&settings, &operations)
+            .err()
+            .unwrap();
+        assert_eq!(
+            vec![
+                "Filter [brightness] amount [-101] must be between -100 and 100",
+                "Filter [rgb] amount [150] must be between -100 and 100"
+            ],
+            errors.0
+        );
+    }
+
+    #[test]
+    fn test_focal_operations_validation() {
+        let settings = ValidationSettings::default();
+        let with_focal = |right| {
+            Operations(vec![crate::infra::image_manipulation::Operation::Resize {
+                width: 100,
+                height: 100,
+                mode: crate::infra::image_manipulation::ResizeMode::Cover,
+                horizontal_align: crate::infra::image_manipulation::HorizontalAlign::Center,
+                vertical_align: crate::infra::image_manipulation::VerticalAlign::Middle,
+                focal: Some(crate::infra::image_manipulation::FocalRegion {
+                    left: 10,
+                    top: 10,
+                    right,
+                    bottom: 20,
+                }),
+                smart: false,
+            }])
+        };
+        assert!(SingletonValidator
+            .validate_operations(&settings, &with_focal(20))
+            .is_ok());
+        let errors = SingletonValidator
+            .validate_operations(&settings, &with_focal(5))
+            .err()
+            .unwrap();
+        assert_eq!(1, errors.0.len());
+        assert!(errors.0[0].starts_with("Focal region"));
+    }
+
+    #[test]
+    fn test_crop_operations_against_source_image_validation() {
+        let settings = ValidationSettings::default();
+        let image = DynamicImage::new(100, 50, image::ColorType::Rgb8);
+        let inside = Operations(vec![crate::infra::image_manipulation::Operation::Crop {
+            left: 0,
+            top: 0,
+            right: 100,
+            bottom: 50,
+        }]);
+        assert!(SingletonValidator
+            .validate_operations_against_source_image(&settings, &inside, &image)
+            .is_ok());
+
+        let outside = Operations(vec![crate::infra::image_manipulation::Operation::Crop {
+            left: 0,
+            top: 0,
+            right: 100,
+            bottom: 51,
+        }]);
+        let errors = SingletonValidator
+            .validate_operations_against_source_image(&settings, &outside, &image)
+            .err()
+            .unwrap();
+        assert_eq!(1, errors.0.len());
+        assert!(errors.0[0].contains("outside of the source image"));
+
+        // Crops happen after trimming, so they have to fit in what trimming leaves
+        let framed = DynamicImage::ImageRgb8(image::RgbImage::from_fn(100, 50, |x, y| {
+            if (10..90).contains(&x) && (10..40).contains(&y) {
+                image::Rgb([0, 0, 0])
+            } else {
+                image::Rgb([255, 255, 255])
+            }
+        }));
+        let trim_then_crop = |right, bottom| {
+            Operations(vec![
+                crate::infra::image_manipulation::Operation::Trim {
+                    from: crate::infra::image_manipulation::TrimFrom::TopLeft,
+                    tolerance: 0,
+                },
+                crate::infra::image_manipulation::Operation::Crop {
+                    left: 0,
+                    top: 0,
+                    right,
+                    bottom,
+                },
+            ])
+        };
+        assert!(SingletonValidator
+            .validate_operations_against_source_image(&settings, &trim_then_crop(80, 30), &framed)
+            .is_ok());
+        let errors = SingletonValidator
+            .validate_operations_against_source_image(&settings, &trim_then_crop(90, 30), &framed)
+            .err()
+            .unwrap();
+        assert_eq!(
+            vec!["Crop box [0x0:90x30] is outside of the trimmed image [80x30]"],
+            errors.0
+        );
+    }
+
+    #[test]
+    fn test_resize_operations_against_source_image_validation() {
+        let settings = ValidationSettings {
+            max_resize_target_width: 1000,
+            max_resize_target_height: 1000,
+            ..ValidationSettings::default()
+        };
+        let resize = |width, height| {
+            Operations(vec![crate::infra::image_manipulation::Operation::Resize {
+                width,
+                height,
+                mode: crate::infra::image_manipulation::ResizeMode::Cover,
+                horizontal_align: Default::default(),
+                vertical_align: Default::default(),
+                focal: None,
+                smart: false,
+            }])
+        };
+        // A side left at 0 follows the (very wide) aspect ratio of the source
+        let wide = DynamicImage::new(1000, 1, image::ColorType::Rgb8);
+        assert!(SingletonValidator
+            .validate_operations_against_source_image(&settings, &resize(1000, 1000), &wide)
+            .is_ok());
+        let errors = SingletonValidator
+            .validate_operations_against_source_image(&settings, &resize(0, 10), &wide)
+            .err()
+            .unwrap();
+        assert_eq!(
+            vec![
+                "Resize to [0x10] comes out at [10000x10], too large, must be [1000x1000] or lower"
+            ],
+            errors.0
+        );
+    }
+
+    #[test]
+    fn test_rotate_and_pad_operations_against_source_image_validation() {
+        use crate::infra::filters::Decimal;
+        use crate::infra::image_manipulation::{Fill, Operation};
+
+        let settings = ValidationSettings {
+            max_resize_target_width: 1000,
+            max_resize_target_height: 1000,
+            ..ValidationSettings::default()
+        };
+        let rotate = |degrees| Operation::Rotate {
+            degrees: Decimal::from_hundredths(degrees * 100),
+            fill: Fill::Transparent,
+        };
+        let pad = |width, height, adaptive| Operation::Pad {
+            width,
+            height,
+            adaptive,
+            horizontal_align: Default::default(),
+            vertical_align: Default::default(),
+            fill: Fill::Transparent,
+        };
+        let validate = |operations: Vec<Operation>, image: &DynamicImage| {
+            SingletonValidator
+                .validate_operations_against_source_image(&settings, &Operations(operations), image)
+                .map_err(|e| e.0)
+        };
+        // Turning by 45 degrees grows each side by up to √2
+        let square = DynamicImage::new(800, 800, image::ColorType::Rgb8);
+        assert!(validate(
+            vec![rotate(45)],
+            &DynamicImage::new(700, 10, image::ColorType::Rgb8)
+        )
+        .is_ok());
+        assert_eq!(
+            Err(vec![
+                "Rotating by [45] degrees comes out at [1131x1131], too large, must be [1000x1000] or lower"
+                    .to_string()
+            ]),
+            validate(vec![rotate(45)], &square)
+        );
+        // Adaptive padding turns the box to match the image, which can take it past the limits
+        let settings = ValidationSettings {
+            max_resize_target_height: 800,
+            ..settings
+        };
+        let validate = |operations: Vec<Operation>, image: &DynamicImage| {
+            SingletonValidator
+                .validate_operations_against_source_image(&settings, &Operations(operations), image)
+                .map_err(|e| e.0)
+        };
+        let tall = DynamicImage::new(10, 700, image::ColorType::Rgb8);
+        assert!(validate(vec![pad(1000, 800, false)], &tall).is_ok());
+        assert_eq!(
+            Err(vec![
+                "Padding to [1000x800] comes out at [800x1000], too large, must be [1000x800] or lower"
+                    .to_string()
+            ]),
+            validate(vec![pad(1000, 800, true)], &tall)
+        );
+        // Right angles swap the sides
+        let tall = DynamicImage::new(10, 900, image::ColorType::Rgb8);
+        assert!(validate(vec![Operation::Rotate90, rotate(0)], &tall).is_ok());
+        assert!(validate(vec![rotate(0)], &tall).is_err());
+    }
+
     #[test]
     fn test_non_empty_good_image_validation() {
         let settings = ValidationSettings::default();
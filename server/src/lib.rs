@@ -11,12 +11,13 @@ mod integration_tests {
     use crate::api::responses::Standard;
     use crate::api::routing::handlers::create_router;
     use crate::infra::components::AppComponents;
-    use crate::infra::config::{Config, ValidationSettings};
+    use crate::infra::config::{Config, OutputSettings, ValidationSettings};
     use crate::test_utils::{localstack_node, s3_client, TestResult};
 
     use super::api::responses::MetadataResponse;
     use super::infra::config::{AuthenticationSettings, AwsSettings, ImageCacheSettings};
     use super::infra::image_caching::*;
+    use super::infra::image_encoding::{EncoderSettings, OutputOptions};
     use super::infra::image_manipulation::Operations;
     use aws_config::{meta::region::RegionProviderChain, BehaviorVersion};
     use axum::{body::Body, http::Request, Router};
@@ -42,10 +43,9 @@ mod integration_tests {
         Ok(())
     }
 
-    const PNG_URL_1: &'static str = "https://beachape.com/images/octopress_with_container.png";
-    const PNG_URL_FOR_VALIDATION_FAILURES: &'static str =
-        "https://beachape.com/images/oh-shit-cat.jpg";
-    const JPG_URL_1: &'static str = "https://beachape.com/images/super-high-performance.jpg";
+    const PNG_URL_1: &str = "https://beachape.com/images/octopress_with_container.png";
+    const PNG_URL_FOR_VALIDATION_FAILURES: &str = "https://beachape.com/images/oh-shit-cat.jpg";
+    const JPG_URL_1: &str = "https://beachape.com/images/super-high-performance.jpg";
 
     #[tokio::test]
     async fn test_resize_png() -> TestResult<()> {
@@ -85,7 +85,7 @@ mod integration_tests {
     async fn test_resize_errors() -> TestResult<()> {
         test_errors(
             false,
-            &PNG_URL_FOR_VALIDATION_FAILURES,
+            PNG_URL_FOR_VALIDATION_FAILURES,
             ImageResize {
                 target_width: 10001,
                 target_height: 10001,
@@ -97,7 +97,7 @@ mod integration_tests {
     #[tokio::test]
     async fn test_metadata_response() -> TestResult<()> {
         test_metadata(
-            &PNG_URL_1,
+            PNG_URL_1,
             ImageResize {
                 target_width: 100,
                 target_height: 80,
@@ -105,7 +105,7 @@ mod integration_tests {
         )
         .await?;
         test_metadata(
-            &PNG_URL_1,
+            PNG_URL_1,
             ImageResize {
                 target_width: -100,
                 target_height: 80,
@@ -113,7 +113,7 @@ mod integration_tests {
         )
         .await?;
         test_metadata(
-            &JPG_URL_1,
+            JPG_URL_1,
             ImageResize {
                 target_width: 100,
                 target_height: -80,
@@ -121,7 +121,7 @@ mod integration_tests {
         )
         .await?;
         test_metadata(
-            &PNG_URL_1,
+            PNG_URL_1,
             ImageResize {
                 target_width: -100,
                 target_height: -80,
@@ -134,7 +134,7 @@ mod integration_tests {
     async fn test_metadata_errors() -> TestResult<()> {
         test_errors(
             true,
-            &PNG_URL_FOR_VALIDATION_FAILURES,
+            PNG_URL_FOR_VALIDATION_FAILURES,
             ImageResize {
                 target_width: 10001,
                 target_height: 10001,
@@ -349,6 +349,9 @@ mod integration_tests {
         let processed_cache_retrieve_req = ImageResizeRequest {
             requested_image_url: image_url.to_string(),
             operations: Operations::build(&Some(resize_target)),
+            output: OutputOptions::default(),
+            encoder: EncoderSettings::resolve(&OutputOptions::default(), &config.output_settings),
+            icc_handling: config.output_settings.icc_handling,
         };
         app_components
             .processed_images_cacher
@@ -411,12 +414,13 @@ mod integration_tests {
                     image_cache_settings,
                     aws_settings,
                     validation_settings: ValidationSettings::default(),
+                    output_settings: OutputSettings::default(),
                 }
             })
             .await
     }
 
-    static UNPROCCESSED_BUCKET_NAME: &'static str = "unprocessed-bucket";
+    static UNPROCCESSED_BUCKET_NAME: &str = "unprocessed-bucket";
     async fn unprocessed_bucket() -> &'static String {
         UNPROCESSED_BUCKET
             .get_or_init(|| async {
@@ -431,7 +435,7 @@ mod integration_tests {
             })
             .await
     }
-    static PROCCESSED_BUCKET_NAME: &'static str = "processed-bucket";
+    static PROCCESSED_BUCKET_NAME: &str = "processed-bucket";
     async fn processed_bucket() -> &'static String {
         PROCESSED_BUCKET
             .get_or_init(|| async {
@@ -48,14 +48,14 @@ pub async fn localstack_node() -> &'static ContainerAsync<LocalStack> {
 static LOCALSTACK_CHANNEL: std::sync::OnceLock<Channel<ContainerCommands>> =
     std::sync::OnceLock::new();
 fn localstack_channel() -> &'static Channel<ContainerCommands> {
-    LOCALSTACK_CHANNEL.get_or_init(|| channel())
+    LOCALSTACK_CHANNEL.get_or_init(channel)
 }
 
 // Holds a channel that we use to block on to messages indicating that the localstack container has been shut down
 static LOCALSTACK_SHUT_DOWN_NOTIFIER_CHANNEL: std::sync::OnceLock<Channel<()>> =
     std::sync::OnceLock::new();
 fn localstack_shut_down_notifier_channel() -> &'static Channel<()> {
-    LOCALSTACK_SHUT_DOWN_NOTIFIER_CHANNEL.get_or_init(|| channel())
+    LOCALSTACK_SHUT_DOWN_NOTIFIER_CHANNEL.get_or_init(channel)
 }
 
 // Holds a static Tokio runtime for blocking ops
@@ -51,10 +51,10 @@ pub enum SignatureError {
 mod tests {
     use crate::signature::*;
 
-    const SECRET: &'static str = "doyouwanttoknowasecretdoyoupromisenottotellwhoaohoh";
-    const PATH: &'static str = "200x-100/https://beachape.com/images/octopress_with_container.png";
+    const SECRET: &str = "doyouwanttoknowasecretdoyoupromisenottotellwhoaohoh";
+    const PATH: &str = "200x-100/https://beachape.com/images/octopress_with_container.png";
     // From https://www.liavaag.org/English/SHA-Generator/HMAC/
-    const EXPECTED_SIGNED_BASE_64: &'static str = "Y/w4HN8q+yZPkR1N1SMJ9gDlCRk=";
+    const EXPECTED_SIGNED_BASE_64: &str = "Y/w4HN8q+yZPkR1N1SMJ9gDlCRk=";
 
     #[test]
     fn test_make_url_safe_base64_hash() -> Result<(), SignatureError> {
@@ -68,6 +68,6 @@ mod tests {
         let signature = EXPECTED_SIGNED_BASE_64.replace("/", "_").replace("+", "-");
         let path_with_signature = format!("/{signature}/{PATH}");
         let hashed = make_url_safe_base64_hash(SECRET, &path_with_signature)?;
-        ensure_signature_is_valid_for_path_and_query(&SECRET, &path_with_signature, &hashed)
+        ensure_signature_is_valid_for_path_and_query(SECRET, &path_with_signature, &hashed)
     }
 }